@@ -0,0 +1,147 @@
+//! Prepares the UI under `static/` for embedding: hashes every asset, points
+//! `index.html` at versioned asset paths (`app/main.<hash>.js`) and
+//! precompresses the text assets so they can be served gzip, brotli or zstd
+//! encoded straight from the binary.
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const COMPRESSIBLE_EXTENSIONS: &[&str] = &["html", "css", "js", "json", "svg", "txt", "map"];
+const INDEX: &str = "app/index.html";
+/// Hex digits of the content hash used in ETags and versioned paths
+const HASH_LENGTH: usize = 16;
+
+fn main() -> io::Result<()> {
+    println!("cargo:rerun-if-changed=static");
+
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo"));
+    let built_dir = out_dir.join("ui");
+    if built_dir.exists() {
+        fs::remove_dir_all(&built_dir)?;
+    }
+    fs::create_dir_all(&built_dir)?;
+
+    let static_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("static");
+    let mut files = Vec::new();
+    collect_files(&static_dir, &mut files)?;
+    files.sort();
+
+    let mut assets = Vec::new();
+    for file in files {
+        let relative = file
+            .strip_prefix(&static_dir)
+            .expect("file is under static")
+            .to_string_lossy()
+            .replace('\\', "/");
+        assets.push((relative, fs::read(&file)?));
+    }
+
+    // index.html refers to the other assets, so it is hashed after rewriting
+    let mut hashes: Vec<(String, String)> = assets
+        .iter()
+        .filter(|(path, _)| path != INDEX)
+        .map(|(path, contents)| (path.clone(), hash(contents)))
+        .collect();
+    if let Some((_, contents)) = assets.iter_mut().find(|(path, _)| path == INDEX) {
+        let mut html = String::from_utf8_lossy(contents).into_owned();
+        for (path, hash) in &hashes {
+            html = html.replace(
+                &format!("\"/ui/{}\"", path),
+                &format!("\"/ui/{}\"", versioned(path, hash)),
+            );
+        }
+        *contents = html.into_bytes();
+        hashes.push((INDEX.to_string(), hash(contents)));
+
+        let target = built_dir.join(INDEX);
+        fs::create_dir_all(target.parent().expect("index has a parent"))?;
+        fs::write(target, contents)?;
+    }
+
+    for (path, contents) in &assets {
+        let compressible = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| COMPRESSIBLE_EXTENSIONS.contains(&extension));
+        if !compressible {
+            continue;
+        }
+
+        let target = built_dir.join(path);
+        fs::create_dir_all(target.parent().expect("file has a parent"))?;
+
+        for (token, encoded) in [
+            ("gz", gzip(contents)?),
+            ("br", brotli(contents)?),
+            ("zst", zstd::bulk::compress(contents, 19)?),
+        ] {
+            // Tiny files can come out larger than they went in
+            if encoded.len() < contents.len() {
+                let mut name = target.clone().into_os_string();
+                name.push(".");
+                name.push(token);
+                fs::write(name, encoded)?;
+            }
+        }
+    }
+
+    let mut manifest = format!("const HASH_LENGTH: usize = {};\n\n", HASH_LENGTH);
+    manifest.push_str("static UI_HASHES: &[(&str, &str)] = &[\n");
+    for (path, hash) in &hashes {
+        manifest.push_str(&format!("    ({:?}, {:?}),\n", path, hash));
+    }
+    manifest.push_str("];\n");
+    fs::write(out_dir.join("ui_hashes.rs"), manifest)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn hash(contents: &[u8]) -> String {
+    Sha256::digest(contents)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>()[..HASH_LENGTH]
+        .to_string()
+}
+
+/// `app/main.js` becomes `app/main.<hash>.js`; `ui.rs` undoes this.
+fn versioned(path: &str, hash: &str) -> String {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let name = match name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{}.{}.{}", stem, hash, extension),
+        None => name.to_string(),
+    };
+    if dir.is_empty() {
+        name
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn gzip(contents: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(contents)?;
+    encoder.finish()
+}
+
+fn brotli(contents: &[u8]) -> io::Result<Vec<u8>> {
+    let params = brotli::enc::BrotliEncoderParams {
+        quality: 11,
+        ..Default::default()
+    };
+    let mut encoded = Vec::new();
+    brotli::BrotliCompress(&mut &contents[..], &mut encoded, &params)?;
+    Ok(encoded)
+}
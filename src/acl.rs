@@ -0,0 +1,104 @@
+use crate::auth::Identity;
+use crate::types::api_path;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Read,
+    Write,
+    Delete,
+    Admin,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AclConfig {
+    #[serde(default)]
+    pub rules: Vec<AclRule>,
+}
+
+/// Grants `allow` to `user` (or `*` for everyone) on `path` and everything below it.
+/// The rule with the longest matching path wins; a user rule beats `*` on a tie.
+#[derive(Debug, Deserialize)]
+pub struct AclRule {
+    pub user: String,
+    pub path: String,
+    pub allow: Vec<Permission>,
+}
+
+static ACL_CONFIG: OnceLock<Option<AclConfig>> = OnceLock::new();
+
+pub fn acl_config() -> Option<&'static AclConfig> {
+    ACL_CONFIG
+        .get_or_init(|| {
+            let path = std::env::var("ACL_FILE").ok()?;
+            let contents = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                eprintln!("Cannot read ACL_FILE='{}': {}", path, e);
+                std::process::exit(1);
+            });
+            let config = serde_json::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("Invalid ACL_FILE='{}': {}", path, e);
+                std::process::exit(1);
+            });
+            Some(config)
+        })
+        .as_ref()
+}
+
+pub fn allowed(identity: &Identity, path: &Path, permission: Permission) -> bool {
+    let Some(config) = acl_config() else {
+        return true;
+    };
+
+    let path = api_path(path);
+    let rule = config
+        .rules
+        .iter()
+        .filter(|rule| applies_to(rule, identity) && is_within(&path, &rule.path))
+        .max_by_key(|rule| (normalize(&rule.path).len(), rule.user != "*"));
+
+    rule.is_some_and(|rule| {
+        rule.allow
+            .iter()
+            .any(|allow| *allow == permission || *allow == Permission::Admin)
+    })
+}
+
+/// Whether `path` should appear in listings, search results and archives:
+/// it is readable, or it is a folder leading to something the caller may use.
+pub fn visible(identity: &Identity, path: &Path) -> bool {
+    if allowed(identity, path, Permission::Read) {
+        return true;
+    }
+
+    let Some(config) = acl_config() else {
+        return true;
+    };
+
+    let relative = api_path(path);
+    config.rules.iter().any(|rule| {
+        applies_to(rule, identity)
+            && !rule.allow.is_empty()
+            && is_within(&rule.path, &relative)
+            && (normalize(&rule.path) != normalize(&relative) || path.is_dir())
+    })
+}
+
+fn applies_to(rule: &AclRule, identity: &Identity) -> bool {
+    rule.user == "*" || rule.user == identity.name
+}
+
+fn is_within(path: &str, prefix: &str) -> bool {
+    let path = normalize(path);
+    let prefix = normalize(prefix);
+
+    prefix.is_empty()
+        || path == prefix
+        || (path.starts_with(prefix) && path.as_bytes().get(prefix.len()) == Some(&b'/'))
+}
+
+fn normalize(path: &str) -> &str {
+    path.trim_matches('/')
+}
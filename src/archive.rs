@@ -0,0 +1,444 @@
+//! Read-only access to the members of zip and tar archives, so they can be
+//! browsed like folders without extracting them.
+
+use crate::types::data_dir;
+use flate2::read::MultiGzDecoder;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+use zip::{CompressionMethod, ZipArchive};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+const EXTENSIONS: &[(&str, ArchiveKind)] = &[
+    (".zip", ArchiveKind::Zip),
+    (".cbz", ArchiveKind::Zip),
+    (".tar", ArchiveKind::Tar),
+    (".cbt", ArchiveKind::Tar),
+    (".tar.gz", ArchiveKind::TarGz),
+    (".tgz", ArchiveKind::TarGz),
+    (".tar.zst", ArchiveKind::TarZst),
+    (".tzst", ArchiveKind::TarZst),
+];
+
+/// Archives whose member index is kept between requests
+const INDEX_CACHE_CAPACITY: usize = 64;
+
+impl ArchiveKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::split_name(path.file_name()?.to_str()?).map(|(kind, _)| kind)
+    }
+
+    /// Splits a file name like `backup.tar.gz` into its kind and `backup`.
+    pub fn split_name(name: &str) -> Option<(Self, &str)> {
+        let lowercase = name.to_ascii_lowercase();
+        EXTENSIONS
+            .iter()
+            .find(|(extension, _)| lowercase.ends_with(extension))
+            .map(|(extension, kind)| (*kind, &name[..name.len() - extension.len()]))
+    }
+}
+
+#[derive(Clone)]
+pub struct ArchiveMember {
+    /// Path inside the archive, `/`-separated without a trailing slash
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    /// Milliseconds since the Unix epoch
+    pub modified: u64,
+}
+
+/// Where the bytes of a file member live.
+pub enum MemberData {
+    /// Stored uncompressed at `offset` in the archive file, so ranges can be
+    /// read directly
+    Stored { offset: u64 },
+    /// Has to be decompressed from the start; `index` is its position in the
+    /// archive for `copy_member`
+    Compressed { index: usize },
+}
+
+/// Splits a path that points into an archive, so `backup.zip/dir/a.txt` gives
+/// the archive file and `dir/a.txt`. `None` unless the closest existing
+/// ancestor is an archive file.
+pub fn locate(path: &Path) -> Option<(PathBuf, ArchiveKind, String)> {
+    let path: PathBuf = path.components().collect();
+    let mut archive = path.as_path();
+    let mut inner = Vec::new();
+
+    loop {
+        if let Ok(metadata) = std::fs::metadata(archive) {
+            if !metadata.is_file() {
+                return None;
+            }
+            let kind = ArchiveKind::from_path(archive)?;
+            inner.reverse();
+            return Some((archive.to_path_buf(), kind, inner.join("/")));
+        }
+
+        inner.push(archive.file_name()?.to_str()?);
+        archive = archive
+            .parent()
+            .filter(|parent| parent.starts_with(data_dir()))?;
+    }
+}
+
+/// Immediate children of the folder `dir` ("" for the top level), including
+/// folders only implied by deeper member paths. `None` when `dir` is not a
+/// folder in the archive.
+pub fn list(
+    archive: &Path,
+    kind: ArchiveKind,
+    dir: &str,
+) -> io::Result<Option<Vec<ArchiveMember>>> {
+    let prefix = if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", dir)
+    };
+    let mut found = dir.is_empty();
+    let mut children = BTreeMap::new();
+
+    for_each_member(archive, kind, |_, member, _| {
+        if member.is_dir && member.name == dir {
+            found = true;
+        }
+        let Some(rest) = member.name.strip_prefix(&prefix) else {
+            return;
+        };
+        if rest.is_empty() {
+            return;
+        }
+        found = true;
+
+        match rest.split_once('/') {
+            Some((child, _)) => {
+                children
+                    .entry(child.to_string())
+                    .or_insert_with(|| ArchiveMember {
+                        name: format!("{}{}", prefix, child),
+                        is_dir: true,
+                        size: 0,
+                        modified: member.modified,
+                    });
+            }
+            None => {
+                children.insert(rest.to_string(), member);
+            }
+        }
+    })?;
+
+    Ok(found.then(|| children.into_values().collect()))
+}
+
+/// Looks up the file member `name`. When a name repeats, the last one wins
+/// as it would on extraction.
+pub fn find(
+    archive: &Path,
+    kind: ArchiveKind,
+    name: &str,
+) -> io::Result<Option<(ArchiveMember, MemberData)>> {
+    let mut found = None;
+    for_each_member(archive, kind, |index, member, offset| {
+        if member.name == name {
+            found = (!member.is_dir).then(|| {
+                let data = match offset {
+                    Some(offset) => MemberData::Stored { offset },
+                    None => MemberData::Compressed { index },
+                };
+                (member, data)
+            });
+        }
+    })?;
+    Ok(found)
+}
+
+/// Writes the decompressed contents of the member at `index` to `out`.
+pub fn copy_member(
+    archive: &Path,
+    kind: ArchiveKind,
+    index: usize,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let file = BufReader::new(File::open(archive)?);
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = ZipArchive::new(file).map_err(io::Error::other)?;
+            let mut member = zip.by_index(index).map_err(io::Error::other)?;
+            io::copy(&mut member, out)?;
+        }
+        ArchiveKind::Tar => {
+            copy_tar_member(tar::Archive::new(file).entries_with_seek()?, index, out)?
+        }
+        ArchiveKind::TarGz => copy_tar_member(
+            tar::Archive::new(MultiGzDecoder::new(file)).entries()?,
+            index,
+            out,
+        )?,
+        ArchiveKind::TarZst => copy_tar_member(
+            tar::Archive::new(zstd::Decoder::with_buffer(file)?).entries()?,
+            index,
+            out,
+        )?,
+    }
+    Ok(())
+}
+
+fn copy_tar_member<R: Read>(
+    mut entries: tar::Entries<'_, R>,
+    index: usize,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut entry = entries.nth(index).ok_or(io::ErrorKind::NotFound)??;
+    io::copy(&mut entry, out)?;
+    Ok(())
+}
+
+type MemberIndex = Arc<Vec<(usize, ArchiveMember, Option<u64>)>>;
+
+struct CachedIndex {
+    modified: Option<SystemTime>,
+    len: u64,
+    members: MemberIndex,
+}
+
+static INDEX_CACHE: OnceLock<Mutex<HashMap<PathBuf, CachedIndex>>> = OnceLock::new();
+
+fn index_cache() -> &'static Mutex<HashMap<PathBuf, CachedIndex>> {
+    INDEX_CACHE.get_or_init(Default::default)
+}
+
+/// Calls `visit` with the position, details and, for members stored
+/// uncompressed, the data offset of every file and folder in the archive.
+/// The index is read once and reused until the archive changes.
+fn for_each_member(
+    archive: &Path,
+    kind: ArchiveKind,
+    mut visit: impl FnMut(usize, ArchiveMember, Option<u64>),
+) -> io::Result<()> {
+    let metadata = std::fs::metadata(archive)?;
+    let modified = metadata.modified().ok();
+    let len = metadata.len();
+    let cached = index_cache()
+        .lock()
+        .unwrap()
+        .get(archive)
+        .filter(|cached| cached.modified == modified && cached.len == len)
+        .map(|cached| cached.members.clone());
+
+    let members = match cached {
+        Some(members) => members,
+        None => {
+            let mut members = Vec::new();
+            scan_members(archive, kind, |index, member, offset| {
+                members.push((index, member, offset))
+            })?;
+            let members = Arc::new(members);
+
+            let mut cache = index_cache().lock().unwrap();
+            if cache.len() >= INDEX_CACHE_CAPACITY && !cache.contains_key(archive) {
+                if let Some(evicted) = cache.keys().next().cloned() {
+                    cache.remove(&evicted);
+                }
+            }
+            cache.insert(
+                archive.to_path_buf(),
+                CachedIndex {
+                    modified,
+                    len,
+                    members: members.clone(),
+                },
+            );
+            members
+        }
+    };
+
+    for (index, member, offset) in members.iter() {
+        visit(*index, member.clone(), *offset);
+    }
+    Ok(())
+}
+
+fn scan_members(
+    archive: &Path,
+    kind: ArchiveKind,
+    mut visit: impl FnMut(usize, ArchiveMember, Option<u64>),
+) -> io::Result<()> {
+    let file = BufReader::new(File::open(archive)?);
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = ZipArchive::new(file).map_err(io::Error::other)?;
+            for index in 0..zip.len() {
+                let member = zip.by_index_raw(index).map_err(io::Error::other)?;
+                if member.is_symlink() {
+                    continue;
+                }
+                let Some(name) = member.enclosed_name().as_deref().and_then(member_name) else {
+                    continue;
+                };
+
+                let stored = member.compression() == CompressionMethod::Stored
+                    && !member.encrypted()
+                    && !member.is_dir();
+                let offset = member.data_start().filter(|_| stored);
+                let modified = member
+                    .last_modified()
+                    .map(|time| {
+                        let days = days_from_civil(
+                            time.year() as i64,
+                            time.month() as i64,
+                            time.day() as i64,
+                        );
+                        let seconds = days * 86400
+                            + time.hour() as i64 * 3600
+                            + time.minute() as i64 * 60
+                            + time.second() as i64;
+                        seconds.max(0) as u64 * 1000
+                    })
+                    .unwrap_or(0);
+
+                let member = ArchiveMember {
+                    name,
+                    is_dir: member.is_dir(),
+                    size: member.size(),
+                    modified,
+                };
+                visit(index, member, offset);
+            }
+        }
+        // Plain tars seek past member data instead of reading through it
+        ArchiveKind::Tar => visit_tar_members(
+            tar::Archive::new(file).entries_with_seek()?,
+            true,
+            &mut visit,
+        )?,
+        ArchiveKind::TarGz => visit_tar_members(
+            tar::Archive::new(MultiGzDecoder::new(file)).entries()?,
+            false,
+            &mut visit,
+        )?,
+        ArchiveKind::TarZst => visit_tar_members(
+            tar::Archive::new(zstd::Decoder::with_buffer(file)?).entries()?,
+            false,
+            &mut visit,
+        )?,
+    }
+    Ok(())
+}
+
+fn visit_tar_members<R: Read>(
+    entries: tar::Entries<'_, R>,
+    seekable: bool,
+    visit: &mut impl FnMut(usize, ArchiveMember, Option<u64>),
+) -> io::Result<()> {
+    for (index, entry) in entries.enumerate() {
+        let entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            continue;
+        }
+        let Some(name) = entry.path().ok().as_deref().and_then(member_name) else {
+            continue;
+        };
+
+        let member = ArchiveMember {
+            name,
+            is_dir: entry_type.is_dir(),
+            size: if entry_type.is_dir() { 0 } else { entry.size() },
+            modified: entry.header().mtime().unwrap_or(0) * 1000,
+        };
+        let offset = (seekable && !member.is_dir).then(|| entry.raw_file_position());
+        visit(index, member, offset);
+    }
+    Ok(())
+}
+
+pub enum EntryType {
+    File,
+    Dir,
+    /// Links, devices and anything else that is never extracted
+    Other,
+}
+
+/// Calls `visit` for every entry in archive order with its name as stored,
+/// its type and a reader over its decompressed contents.
+pub fn read_entries<R: Read + Seek>(
+    reader: R,
+    kind: ArchiveKind,
+    mut visit: impl FnMut(&str, EntryType, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = ZipArchive::new(reader).map_err(io::Error::other)?;
+            for index in 0..zip.len() {
+                let mut member = zip.by_index(index).map_err(io::Error::other)?;
+                let entry_type = if member.is_symlink() {
+                    EntryType::Other
+                } else if member.is_dir() {
+                    EntryType::Dir
+                } else {
+                    EntryType::File
+                };
+                let name = member.name().to_string();
+                visit(&name, entry_type, &mut member)?;
+            }
+            Ok(())
+        }
+        ArchiveKind::Tar => read_tar_entries(reader, visit),
+        ArchiveKind::TarGz => read_tar_entries(MultiGzDecoder::new(reader), visit),
+        ArchiveKind::TarZst => read_tar_entries(zstd::Decoder::new(reader)?, visit),
+    }
+}
+
+fn read_tar_entries(
+    reader: impl Read,
+    mut visit: impl FnMut(&str, EntryType, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+        let entry_type = if entry_type.is_file() {
+            EntryType::File
+        } else if entry_type.is_dir() {
+            EntryType::Dir
+        } else {
+            EntryType::Other
+        };
+        let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        visit(&name, entry_type, &mut entry)?;
+    }
+    Ok(())
+}
+
+/// Normalises a member path, rejecting anything that would escape the archive.
+pub fn member_name(path: &Path) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
@@ -0,0 +1,254 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+use warp::http::StatusCode;
+use warp::hyper::Body;
+use warp::{Filter, Rejection};
+
+const REALM: &str = "mediabrowser";
+/// Verified credentials remembered per cache before one is dropped
+const VERIFIED_CAPACITY: usize = 1024;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub users: Vec<UserEntry>,
+    #[serde(default)]
+    pub tokens: Vec<TokenEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserEntry {
+    pub name: String,
+    /// Argon2 PHC string, e.g. the output of `mediabrowser hash-password`
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenEntry {
+    pub name: String,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+static AUTH_CONFIG: OnceLock<Option<AuthConfig>> = OnceLock::new();
+
+// Digests of successful `Authorization` headers, so argon2 runs once per credential
+static VERIFIED: OnceLock<Mutex<HashMap<[u8; 32], String>>> = OnceLock::new();
+
+// Digests of successful (hash, password) pairs for secrets checked outside the user list
+static VERIFIED_SECRETS: OnceLock<Mutex<HashSet<[u8; 32]>>> = OnceLock::new();
+
+// Random per process, so cached digests cannot be checked against guesses offline
+static DIGEST_KEY: OnceLock<[u8; 32]> = OnceLock::new();
+
+// Checked in place of a real hash for unknown users, so they take as long to reject
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+pub fn auth_config() -> Option<&'static AuthConfig> {
+    AUTH_CONFIG
+        .get_or_init(|| {
+            let path = std::env::var("AUTH_FILE").ok()?;
+            let contents = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                eprintln!("Cannot read AUTH_FILE='{}': {}", path, e);
+                std::process::exit(1);
+            });
+            let config = serde_json::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("Invalid AUTH_FILE='{}': {}", path, e);
+                std::process::exit(1);
+            });
+            DUMMY_HASH.get_or_init(|| hash_password("").expect("failed to hash dummy password"));
+            Some(config)
+        })
+        .as_ref()
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|e| e.to_string())?;
+    let salt = SaltString::encode_b64(&bytes).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+pub async fn verify_password_cached(password: &str, hash: &str) -> bool {
+    let verified = VERIFIED_SECRETS.get_or_init(|| Mutex::new(HashSet::new()));
+    let key = digest(&[hash, password]);
+    if verified.lock().unwrap().contains(&key) {
+        return true;
+    }
+
+    let (check_hash, check_password) = (hash.to_string(), password.to_string());
+    let valid = tokio::task::spawn_blocking(move || verify_password(&check_password, &check_hash))
+        .await
+        .unwrap_or(false);
+    if valid {
+        let mut verified = verified.lock().unwrap();
+        if verified.len() >= VERIFIED_CAPACITY {
+            if let Some(evicted) = verified.iter().next().copied() {
+                verified.remove(&evicted);
+            }
+        }
+        verified.insert(key);
+    }
+    valid
+}
+
+/// Keyed SHA-256 of `parts`, so cached credentials are never kept as sent.
+fn digest(parts: &[&str]) -> [u8; 32] {
+    let key = DIGEST_KEY.get_or_init(|| {
+        let mut key = [0u8; 32];
+        getrandom::getrandom(&mut key).expect("failed to read system randomness");
+        key
+    });
+    let mut hasher = Sha256::new();
+    hasher.update(key);
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher.finalize().into()
+}
+
+/// Splits a `Basic` authorization header into user and password.
+pub fn basic_credentials(header: &str) -> Option<(String, String)> {
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// Hex-encoded random token suitable for unguessable URLs.
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 24];
+    getrandom::getrandom(&mut bytes).expect("failed to read system randomness");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Resolves the caller of a request, rejecting with 401 when credentials are
+/// missing or wrong. Without `AUTH_FILE` every request is anonymous.
+pub fn authenticated() -> impl Filter<Extract = (Identity,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization").and_then(
+        |header: Option<String>| async move {
+            match auth_config() {
+                None => Ok(Identity {
+                    name: "anonymous".to_string(),
+                }),
+                Some(config) => match header {
+                    Some(header) => authenticate(config, header)
+                        .await
+                        .ok_or_else(|| warp::reject::custom(Unauthorized)),
+                    None => Err(warp::reject::custom(Unauthorized)),
+                },
+            }
+        },
+    )
+}
+
+pub fn required() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    authenticated().map(|_| ()).untuple_one()
+}
+
+pub async fn handle_rejection(err: Rejection) -> Result<warp::reply::Response, Rejection> {
+    if err.find::<Unauthorized>().is_some() {
+        return Ok(unauthorized_response());
+    }
+
+    Err(err)
+}
+
+pub fn unauthorized_response() -> warp::reply::Response {
+    challenge_response(REALM)
+}
+
+pub fn challenge_response(realm: &str) -> warp::reply::Response {
+    warp::http::Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(
+            "www-authenticate",
+            format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm),
+        )
+        .header("content-type", "application/json")
+        .body(Body::from("\"Authentication required\""))
+        .unwrap()
+}
+
+async fn authenticate(config: &'static AuthConfig, header: String) -> Option<Identity> {
+    let verified = VERIFIED.get_or_init(|| Mutex::new(HashMap::new()));
+    let key = digest(&[&header]);
+    if let Some(name) = verified.lock().unwrap().get(&key) {
+        return Some(Identity { name: name.clone() });
+    }
+
+    let name = if let Some(token) = header.strip_prefix("Bearer ") {
+        // Every token is compared, as digests of equal length, so timing
+        // reveals neither which one matched nor how long they are
+        let token = digest(&[token.trim()]);
+        config
+            .tokens
+            .iter()
+            .fold(None, |matched, entry| {
+                let equal = constant_time_eq(&digest(&[&entry.token]), &token);
+                if equal {
+                    Some(entry)
+                } else {
+                    matched
+                }
+            })
+            .map(|entry| entry.name.clone())?
+    } else {
+        let (user, password) = basic_credentials(&header)?;
+        let entry = config.users.iter().find(|entry| entry.name == user);
+
+        let hash = match entry {
+            Some(entry) => entry.password.clone(),
+            None => DUMMY_HASH.get()?.clone(),
+        };
+        let valid = tokio::task::spawn_blocking(move || verify_password(&password, &hash))
+            .await
+            .unwrap_or(false);
+        match entry {
+            Some(entry) if valid => entry.name.clone(),
+            _ => return None,
+        }
+    };
+
+    let mut verified = verified.lock().unwrap();
+    if verified.len() >= VERIFIED_CAPACITY {
+        if let Some(evicted) = verified.keys().next().copied() {
+            verified.remove(&evicted);
+        }
+    }
+    verified.insert(key, name.clone());
+    Some(Identity { name })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,29 @@
+use crate::types::read_only;
+use serde::Serialize;
+use std::convert::Infallible;
+use warp::http::StatusCode;
+use warp::Reply;
+
+#[derive(Serialize)]
+struct Capabilities {
+    read_only: bool,
+}
+
+pub async fn handle_capabilities() -> Result<warp::reply::Response, Infallible> {
+    Ok(warp::reply::json(&Capabilities {
+        read_only: read_only(),
+    })
+    .into_response())
+}
+
+pub fn read_only_response() -> warp::reply::Response {
+    warp::reply::with_header(
+        warp::reply::with_status(
+            warp::reply::json(&"Server is in read-only mode"),
+            StatusCode::METHOD_NOT_ALLOWED,
+        ),
+        "allow",
+        "GET, HEAD",
+    )
+    .into_response()
+}
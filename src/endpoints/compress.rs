@@ -0,0 +1,175 @@
+use super::download_bulk::{collect_archive_entries, write_archive, ArchiveFormat};
+use super::jobs::{accepted_response, JobHandle, JobKind};
+use super::upload::{PartialFile, UploadStatus};
+use crate::acl::{self, Permission};
+use crate::auth::Identity;
+use crate::types::{api_path, data_dir, data_path, ConflictPolicy};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use tokio::runtime::Handle;
+use warp::http::StatusCode;
+use warp::Reply;
+
+const COMPRESS_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Deserialize)]
+pub struct CompressRequest {
+    pub paths: Vec<String>,
+    /// Archive file to create, relative to the data directory
+    pub destination: String,
+    /// Defaults to the format named by the destination's extension
+    pub format: Option<ArchiveFormat>,
+    /// Compression level for `tar.gz` (0-9) and `tar.zst` (1-22)
+    pub level: Option<i32>,
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+}
+
+pub async fn handle_compress(
+    request: CompressRequest,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    let mut data_paths = Vec::new();
+    for path in &request.paths {
+        let path = path.trim();
+        if path.is_empty() {
+            continue;
+        }
+
+        let Some(full_path) = data_path(path) else {
+            return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+        };
+        data_paths.push(full_path);
+    }
+
+    if data_paths.is_empty() {
+        return Ok(json_response("No files specified", StatusCode::BAD_REQUEST));
+    }
+
+    let destination = request.destination.trim().trim_end_matches('/');
+    let named_format = ArchiveFormat::from_file_name(destination);
+    let format = request.format.or(named_format).unwrap_or_default();
+    // Add the extension unless the name already carries the right one
+    let destination = if named_format == Some(format) {
+        destination.to_string()
+    } else {
+        format!("{}.{}", destination, format.extension())
+    };
+
+    let Some(level) = format.compression_level(request.level) else {
+        return Ok(json_response(
+            "Invalid compression level",
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+
+    let Some(archive_path) = data_path(&destination) else {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    };
+
+    if archive_path.parent().is_none() || archive_path.file_name().is_none() {
+        return Ok(json_response(
+            "Invalid destination",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    if !acl::allowed(&identity, &archive_path, Permission::Write) {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    }
+
+    let source = data_paths
+        .iter()
+        .map(|path| api_path(path))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let job = JobHandle::start(
+        JobKind::Compress,
+        &identity,
+        source,
+        api_path(&archive_path),
+    );
+
+    tokio::task::spawn_blocking({
+        let job = job.clone();
+        move || {
+            let result = compress_paths(
+                &data_paths,
+                &archive_path,
+                format,
+                level,
+                request.on_conflict,
+                &identity,
+                &job,
+            );
+            job.finish(result.map_err(|e| e.to_string()));
+        }
+    });
+
+    Ok(accepted_response(&job))
+}
+
+/// Archives `paths` into a hidden partial file next to `archive_path` and
+/// moves it into place once complete.
+fn compress_paths(
+    paths: &[PathBuf],
+    archive_path: &Path,
+    format: ArchiveFormat,
+    level: i32,
+    on_conflict: ConflictPolicy,
+    identity: &Identity,
+    job: &JobHandle,
+) -> std::io::Result<()> {
+    let runtime = Handle::current();
+    let (Some(dir), Some(filename)) = (
+        archive_path.parent(),
+        archive_path.file_name().and_then(|name| name.to_str()),
+    ) else {
+        return Err(std::io::ErrorKind::InvalidInput.into());
+    };
+
+    let entries = collect_archive_entries(paths, data_dir(), &|path: &Path| {
+        acl::visible(identity, path)
+    })?;
+    let bytes_total = entries.iter().map(|entry| entry.file_size()).sum();
+    job.update(|job| job.bytes_total = bytes_total);
+
+    std::fs::create_dir_all(dir)?;
+    let (file, partial) = runtime.block_on(PartialFile::create(dir))?;
+    let file = runtime.block_on(file.into_std());
+
+    let mut writer = BufWriter::with_capacity(COMPRESS_BUFFER_SIZE, file);
+    write_archive(format, level, &entries, &mut writer, &|read| {
+        job.update(|job| job.bytes_done += read)
+    })?;
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    drop(file);
+
+    let (status, final_path) = runtime.block_on(partial.place(dir, filename, on_conflict))?;
+    match status {
+        UploadStatus::Conflict => Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", api_path(archive_path)),
+        )),
+        UploadStatus::Skipped => {
+            job.skip(format!("{}: already exists", api_path(archive_path)));
+            Ok(())
+        }
+        _ => {
+            job.update(|job| {
+                job.entries = entries.len() as u64;
+                if let Some(final_path) = &final_path {
+                    job.target = api_path(final_path);
+                }
+            });
+            Ok(())
+        }
+    }
+}
+
+fn json_response(message: &str, status: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&message), status).into_response()
+}
@@ -0,0 +1,187 @@
+//! Validators (`ETag`, `Last-Modified`) for served files and the evaluation
+//! of conditional request headers against them, as in RFC 9110 section 13.
+
+use std::fs::Metadata;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use warp::http::{response, HeaderMap, StatusCode};
+use warp::hyper::Body;
+
+pub(crate) struct Validators {
+    pub etag: String,
+    pub last_modified: Option<SystemTime>,
+}
+
+/// What to do with a request after checking its preconditions.
+pub(crate) enum Precondition {
+    /// Serve the content; `range` is false when `If-Range` no longer matches
+    /// and the whole representation must be sent instead
+    Proceed {
+        range: bool,
+    },
+    NotModified,
+    Failed,
+}
+
+impl Validators {
+    /// Validators for the bytes of a file, or with a non-empty `part` naming
+    /// it for one member of an archive file.
+    pub fn for_file(metadata: &Metadata, part: &str) -> Self {
+        let modified = metadata.modified().ok();
+        let nanos = modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+
+        let mut etag = format!("{:x}-{:x}-{:x}", inode(metadata), metadata.len(), nanos);
+        if !part.is_empty() {
+            etag.push('-');
+            etag.push_str(part);
+        }
+
+        // A file changed within the last second may change again without its
+        // timestamp moving on filesystems with coarse times, so only promise
+        // semantic equivalence until it settles
+        let recent = modified
+            .and_then(|time| SystemTime::now().duration_since(time).ok())
+            .is_none_or(|age| age < Duration::from_secs(1));
+        let etag = if recent {
+            format!("W/\"{}\"", etag)
+        } else {
+            format!("\"{}\"", etag)
+        };
+
+        Self {
+            etag,
+            last_modified: modified,
+        }
+    }
+
+    /// Validators for content known by a hash of its bytes, such as the
+    /// assets embedded in the binary.
+    pub fn for_hash(hash: &str) -> Self {
+        Self {
+            etag: format!("\"{}\"", hash),
+            last_modified: None,
+        }
+    }
+
+    /// Validators for a copy compressed with the `Content-Encoding` `token`,
+    /// which is a different representation and so needs its own tag.
+    pub fn encoded(mut self, token: &str) -> Self {
+        let end = self.etag.len() - 1;
+        self.etag.insert_str(end, &format!("-{}", token));
+        self
+    }
+
+    /// Adds `ETag` and `Last-Modified` to a response.
+    pub fn apply(&self, builder: response::Builder) -> response::Builder {
+        let builder = builder.header("etag", &self.etag);
+        match self.last_modified {
+            Some(time) => builder.header("last-modified", httpdate::fmt_http_date(time)),
+            None => builder,
+        }
+    }
+
+    /// Checks `If-Match`, `If-Unmodified-Since`, `If-None-Match`,
+    /// `If-Modified-Since` and, when a range was asked for, `If-Range`, in
+    /// the order RFC 9110 gives them precedence.
+    pub fn evaluate(&self, headers: &HeaderMap) -> Precondition {
+        if let Some(value) = header(headers, "if-match") {
+            if !self.matches(value, true) {
+                return Precondition::Failed;
+            }
+        } else if let Some(date) = header(headers, "if-unmodified-since")
+            .and_then(parse_date)
+            // Without a modification date there is nothing to compare against
+            .filter(|_| self.last_modified.is_some())
+        {
+            if self.modified_since(date) {
+                return Precondition::Failed;
+            }
+        }
+
+        if let Some(value) = header(headers, "if-none-match") {
+            if self.matches(value, false) {
+                return Precondition::NotModified;
+            }
+        } else if let Some(date) = header(headers, "if-modified-since").and_then(parse_date) {
+            if !self.modified_since(date) {
+                return Precondition::NotModified;
+            }
+        }
+
+        let range = match header(headers, "if-range").map(str::trim) {
+            Some(_) if headers.get("range").is_none() => true,
+            Some(value) if value.starts_with('"') || value.starts_with("W/") => {
+                !self.is_weak() && value == self.etag
+            }
+            // A date only validates when it is exactly our (strong) timestamp
+            Some(value) => parse_date(value)
+                .is_some_and(|date| !self.is_weak() && self.seconds() == Some(seconds(date))),
+            None => true,
+        };
+        Precondition::Proceed { range }
+    }
+
+    /// `304 Not Modified` or `412 Precondition Failed` carrying the
+    /// validators.
+    pub fn response(&self, status: StatusCode) -> warp::reply::Response {
+        self.apply(warp::http::Response::builder().status(status))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn is_weak(&self) -> bool {
+        self.etag.starts_with("W/")
+    }
+
+    /// Compares against a list of entity tags; `If-Match` needs the strong
+    /// comparison, `If-None-Match` the weak one.
+    fn matches(&self, list: &str, strong: bool) -> bool {
+        if list.trim() == "*" {
+            return true;
+        }
+        if strong && self.is_weak() {
+            return false;
+        }
+        let own = self.etag.trim_start_matches("W/");
+        list.split(',')
+            .map(str::trim)
+            .filter(|tag| !(strong && tag.starts_with("W/")))
+            .any(|tag| tag.trim_start_matches("W/") == own)
+    }
+
+    fn modified_since(&self, date: SystemTime) -> bool {
+        self.seconds()
+            .is_none_or(|modified| modified > seconds(date))
+    }
+
+    /// HTTP dates have whole seconds, so comparisons drop the fraction.
+    fn seconds(&self) -> Option<u64> {
+        self.last_modified.map(seconds)
+    }
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn parse_date(value: &str) -> Option<SystemTime> {
+    httpdate::parse_http_date(value.trim()).ok()
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> u64 {
+    0
+}
@@ -1,10 +1,16 @@
-use crate::types::{data_path, FileQuery};
+use crate::acl::{self, Permission};
+use crate::auth::Identity;
+use crate::types::{data_dir, data_path, FileQuery};
 use percent_encoding::percent_decode_str;
 use std::convert::Infallible;
+use std::path::{Path, PathBuf};
 use tokio::fs;
 use warp::http::StatusCode;
 
-pub async fn handle_delete(query: FileQuery) -> Result<impl warp::Reply, Infallible> {
+pub async fn handle_delete(
+    query: FileQuery,
+    identity: Identity,
+) -> Result<impl warp::Reply, Infallible> {
     let decoded_path = percent_decode_str(&query.path).decode_utf8_lossy();
     let Some(file_path) = data_path(decoded_path.as_ref()) else {
         return Ok(warp::reply::with_status(
@@ -13,13 +19,21 @@ pub async fn handle_delete(query: FileQuery) -> Result<impl warp::Reply, Infalli
         ));
     };
 
-    let result = if file_path.is_dir() {
-        fs::remove_dir_all(&file_path).await
-    } else {
-        fs::remove_file(&file_path).await
-    };
+    if !acl::allowed(&identity, &file_path, Permission::Delete) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"Access denied"),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+
+    if is_data_root(&file_path).await {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"Cannot delete the root folder"),
+            StatusCode::FORBIDDEN,
+        ));
+    }
 
-    match result {
+    match remove_path(&file_path).await {
         Ok(_) => Ok(warp::reply::with_status(
             warp::reply::json(&"Deleted successfully"),
             StatusCode::OK,
@@ -30,3 +44,29 @@ pub async fn handle_delete(query: FileQuery) -> Result<impl warp::Reply, Infalli
         )),
     }
 }
+
+pub(crate) async fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path).await
+    } else {
+        fs::remove_file(path).await
+    }
+}
+
+pub(crate) async fn is_data_root(path: &Path) -> bool {
+    resolved(path).await == resolved(data_dir()).await
+}
+
+/// `path` with symlinks and `.` resolved, as far as it exists.
+pub(crate) async fn resolved(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path).await {
+        return path;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => match fs::canonicalize(parent).await {
+            Ok(parent) => parent.join(name),
+            Err(_) => path.to_path_buf(),
+        },
+        _ => path.to_path_buf(),
+    }
+}
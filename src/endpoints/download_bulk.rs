@@ -1,30 +1,151 @@
-use crate::types::{api_path, data_path};
+use crate::acl;
+use crate::archive::ArchiveKind;
+use crate::auth::Identity;
+use crate::types::{data_dir, data_path, is_internal};
+use crate::zipstream::{self, ZipStream};
 use bytes::Bytes;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use futures_util::stream;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use serde::Deserialize;
 use std::convert::Infallible;
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use tar::Builder;
+use std::time::UNIX_EPOCH;
+use tar::{Builder, Header, HeaderMode};
 use tokio::sync::mpsc;
+use walkdir::WalkDir;
 use warp::hyper::Body;
 use warp::{http::StatusCode, Reply};
 
 const STREAM_CHUNK_SIZE: usize = 64 * 1024;
 const STREAM_CHANNEL_CAPACITY: usize = 4;
 
+/// Characters RFC 5987 allows unescaped in `filename*`.
+const DISPOSITION_FILENAME: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
 #[derive(Deserialize)]
 pub struct DownloadBulkRequest {
     pub paths: Vec<String>,
+    #[serde(default)]
+    pub format: ArchiveFormat,
+    /// Compression level for `tar.gz` (0-9) and `tar.zst` (1-22)
+    pub level: Option<i32>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz", alias = "tgz")]
+    TarGz,
+    #[serde(rename = "tar.zst", alias = "tzst")]
+    TarZst,
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Format implied by a file name such as `backup.tar.gz`.
+    pub(crate) fn from_file_name(name: &str) -> Option<Self> {
+        ArchiveKind::split_name(name).map(|(kind, _)| match kind {
+            ArchiveKind::Zip => Self::Zip,
+            ArchiveKind::Tar => Self::Tar,
+            ArchiveKind::TarGz => Self::TarGz,
+            ArchiveKind::TarZst => Self::TarZst,
+        })
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "tar" => Some(Self::Tar),
+            "tar.gz" | "tgz" => Some(Self::TarGz),
+            "tar.zst" | "tzst" => Some(Self::TarZst),
+            "zip" => Some(Self::Zip),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+            Self::TarZst => "tar.zst",
+            Self::Zip => "zip",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Tar => "application/x-tar",
+            Self::TarGz => "application/gzip",
+            Self::TarZst => "application/zstd",
+            Self::Zip => "application/zip",
+        }
+    }
+
+    /// Picks the compression level, `None` when `level` is out of range.
+    /// TAR and ZIP are always stored uncompressed, so they take no level.
+    pub(crate) fn compression_level(self, level: Option<i32>) -> Option<i32> {
+        let (range, default) = match self {
+            Self::TarGz => (0..=9, 6),
+            Self::TarZst => (1..=22, 3),
+            Self::Tar | Self::Zip => return level.is_none().then_some(0),
+        };
+
+        match level {
+            Some(level) if range.contains(&level) => Some(level),
+            Some(_) => None,
+            None => Some(default),
+        }
+    }
 }
 
 pub async fn handle_downloads(
     request: DownloadBulkRequest,
+    identity: Identity,
 ) -> Result<warp::reply::Response, Infallible> {
-    create_tar_response(request.paths)
+    create_archive_response(
+        request.paths,
+        data_dir().to_path_buf(),
+        request.format,
+        request.level,
+        false,
+        move |path| acl::visible(&identity, path),
+    )
+    .await
 }
 
-fn create_tar_response(paths: Vec<String>) -> Result<warp::reply::Response, Infallible> {
+/// Streams `paths` as an archive whose entry names are relative to `root`.
+pub(crate) async fn create_archive_response(
+    paths: Vec<String>,
+    root: PathBuf,
+    format: ArchiveFormat,
+    level: Option<i32>,
+    head: bool,
+    visible: impl Fn(&Path) -> bool + Send + 'static,
+) -> Result<warp::reply::Response, Infallible> {
+    let Some(level) = format.compression_level(level) else {
+        return Ok(
+            warp::reply::with_status("Invalid compression level", StatusCode::BAD_REQUEST)
+                .into_response(),
+        );
+    };
+
     let mut data_paths = Vec::new();
 
     for path in paths {
@@ -48,29 +169,113 @@ fn create_tar_response(paths: Vec<String>) -> Result<warp::reply::Response, Infa
         );
     }
 
-    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(STREAM_CHANNEL_CAPACITY);
-    tokio::task::spawn_blocking({
-        let tx = tx;
-        move || {
-            let result = build_tar_stream(&data_paths, tx.clone());
-            if let Err(e) = result {
-                let _ = tx.blocking_send(Err(e));
+    let filename = format!(
+        "{}.{}",
+        archive_name(&data_paths, &root),
+        format.extension()
+    );
+
+    // Entries are listed up front so the exact archive size is known before streaming
+    let entries = match tokio::task::spawn_blocking(move || {
+        collect_archive_entries(&data_paths, &root, &visible)
+    })
+    .await
+    {
+        Ok(Ok(entries)) => entries,
+        _ => {
+            return Ok(warp::reply::with_status(
+                "Failed to read files",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response());
+        }
+    };
+
+    let content_length = match format {
+        ArchiveFormat::Tar => Some(tar_size(&entries)),
+        ArchiveFormat::Zip => Some(zipstream::archive_size(entries.iter().map(|entry| {
+            let size = entry.metadata.is_file().then_some(entry.metadata.len());
+            (entry.name.as_str(), size)
+        }))),
+        ArchiveFormat::TarGz | ArchiveFormat::TarZst => None,
+    };
+
+    let body = if head {
+        Body::empty()
+    } else {
+        blocking_body(move |writer| write_archive(format, level, &entries, writer, &|_| {}))
+    };
+
+    let mut response = warp::http::Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", format.content_type())
+        .header("content-disposition", attachment_disposition(&filename));
+    if let Some(length) = content_length {
+        response = response.header("content-length", length.to_string());
+    }
+
+    Ok(response.body(body).unwrap())
+}
+
+/// Names the archive after the single selected item, or the closest folder
+/// containing every selected item, falling back to "download" at the root.
+fn archive_name(paths: &[PathBuf], root: &Path) -> String {
+    let mut common = match paths {
+        [single] => single.clone(),
+        [first, ..] => first.parent().unwrap_or(root).to_path_buf(),
+        [] => root.to_path_buf(),
+    };
+    for path in paths {
+        while !path.starts_with(&common) {
+            if !common.pop() {
+                break;
             }
         }
+    }
+
+    if common.starts_with(root) && common != root {
+        if let Some(name) = common.file_name() {
+            return name.to_string_lossy().into_owned();
+        }
+    }
+    "download".to_string()
+}
+
+/// `filename` is limited to ASCII, so non-ASCII names also go in `filename*`.
+fn attachment_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(filename, DISPOSITION_FILENAME)
+    )
+}
+
+/// Runs `write` on a blocking thread and streams what it writes as a body.
+/// Errors abort the body so the client sees a truncated response.
+pub(crate) fn blocking_body(
+    write: impl FnOnce(&mut dyn Write) -> std::io::Result<()> + Send + 'static,
+) -> Body {
+    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(STREAM_CHANNEL_CAPACITY);
+    tokio::task::spawn_blocking(move || {
+        let mut writer =
+            BufWriter::with_capacity(STREAM_CHUNK_SIZE, ChannelWriter::new(tx.clone()));
+        let result = write(&mut writer).and_then(|()| writer.flush());
+        if let Err(e) = result {
+            let _ = tx.blocking_send(Err(e));
+        }
     });
 
     let stream = stream::unfold(rx, |mut rx| async move {
         rx.recv().await.map(|item| (item, rx))
     });
-    let body = Body::wrap_stream(stream);
-    let disposition = "attachment; filename=\"download.tar\"";
-
-    Ok(warp::http::Response::builder()
-        .status(StatusCode::OK)
-        .header("content-type", "application/x-tar")
-        .header("content-disposition", disposition)
-        .body(body)
-        .unwrap())
+    Body::wrap_stream(stream)
 }
 
 struct ChannelWriter {
@@ -102,35 +307,224 @@ impl Write for ChannelWriter {
     }
 }
 
-fn build_tar_stream(
-    paths: &[PathBuf],
-    tx: mpsc::Sender<Result<Bytes, std::io::Error>>,
+pub(crate) struct ArchiveEntry {
+    path: PathBuf,
+    /// Entry name relative to the archive root, `/`-separated
+    name: String,
+    metadata: std::fs::Metadata,
+}
+
+impl ArchiveEntry {
+    pub(crate) fn file_size(&self) -> u64 {
+        if self.metadata.is_file() {
+            self.metadata.len()
+        } else {
+            0
+        }
+    }
+}
+
+/// Writes `entries` as an archive, calling `progress` with the number of
+/// file bytes read as it goes.
+pub(crate) fn write_archive(
+    format: ArchiveFormat,
+    level: i32,
+    entries: &[ArchiveEntry],
+    writer: impl Write,
+    progress: &dyn Fn(u64),
 ) -> std::io::Result<()> {
-    let writer = ChannelWriter::new(tx);
-    let buffered = BufWriter::with_capacity(STREAM_CHUNK_SIZE, writer);
-    let mut tar = Builder::new(buffered);
+    let mut output = match format {
+        ArchiveFormat::Tar => build_tar_stream(entries, writer, progress)?,
+        ArchiveFormat::TarGz => {
+            let encoder = GzEncoder::new(writer, Compression::new(level as u32));
+            build_tar_stream(entries, encoder, progress)?.finish()?
+        }
+        ArchiveFormat::TarZst => {
+            let encoder = zstd::Encoder::new(writer, level)?;
+            build_tar_stream(entries, encoder, progress)?.finish()?
+        }
+        ArchiveFormat::Zip => build_zip_stream(entries, writer, progress)?,
+    };
+    output.flush()
+}
+
+fn build_tar_stream<W: Write>(
+    entries: &[ArchiveEntry],
+    writer: W,
+    progress: &dyn Fn(u64),
+) -> std::io::Result<W> {
+    let mut tar = Builder::new(writer);
+
+    for entry in entries {
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(&entry.metadata, HeaderMode::Complete);
+        if entry.metadata.is_dir() {
+            tar.append_data(&mut header, &entry.name, std::io::empty())?;
+        } else {
+            let file = ExactReader::new(
+                std::fs::File::open(&entry.path)?,
+                entry.metadata.len(),
+                progress,
+            );
+            tar.append_data(&mut header, &entry.name, file)?;
+        }
+    }
+
+    tar.into_inner()
+}
+
+fn build_zip_stream<W: Write>(
+    entries: &[ArchiveEntry],
+    writer: W,
+    progress: &dyn Fn(u64),
+) -> std::io::Result<W> {
+    let mut zip = ZipStream::new(writer);
+
+    for entry in entries {
+        let modified = entry.metadata.modified().unwrap_or(UNIX_EPOCH);
+        let mode = file_mode(&entry.metadata);
+        if entry.metadata.is_dir() {
+            zip.add_directory(&entry.name, modified, mode)?;
+        } else {
+            let size = entry.metadata.len();
+            let file = ExactReader::new(std::fs::File::open(&entry.path)?, size, progress);
+            zip.add_file(&entry.name, modified, mode, size, file)?;
+        }
+    }
+
+    zip.finish()
+}
+
+/// Size of the archive `build_tar_stream` writes: a 512-byte header per entry,
+/// a GNU long-name entry before names over 100 bytes, file data padded to
+/// 512 bytes, and two zero blocks at the end.
+fn tar_size(entries: &[ArchiveEntry]) -> u64 {
+    fn padded(len: u64) -> u64 {
+        len.div_ceil(512) * 512
+    }
+
+    let entries_size: u64 = entries
+        .iter()
+        .map(|entry| {
+            let name_len = entry.name.len() as u64;
+            let long_name = if name_len > 100 {
+                512 + padded(name_len + 1)
+            } else {
+                0
+            };
+            let data = if entry.metadata.is_file() {
+                padded(entry.metadata.len())
+            } else {
+                0
+            };
+            long_name + 512 + data
+        })
+        .sum();
+
+    entries_size + 1024
+}
+
+/// Yields exactly `len` bytes, failing if the file shrank since it was listed
+/// so the advertised length is never silently padded or exceeded.
+struct ExactReader<'a, R> {
+    inner: std::io::Take<R>,
+    progress: &'a dyn Fn(u64),
+}
+
+impl<'a, R: Read> ExactReader<'a, R> {
+    fn new(inner: R, len: u64, progress: &'a dyn Fn(u64)) -> Self {
+        Self {
+            inner: inner.take(len),
+            progress,
+        }
+    }
+}
+
+impl<R: Read> Read for ExactReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if read == 0 && !buf.is_empty() && self.inner.limit() > 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "file changed while archiving",
+            ));
+        }
+        (self.progress)(read as u64);
+        Ok(read)
+    }
+}
+
+/// Lists every visible file and folder under `paths`, parents before
+/// children, with the entry name relative to `root`.
+pub(crate) fn collect_archive_entries(
+    paths: &[PathBuf],
+    root: &Path,
+    visible: &impl Fn(&Path) -> bool,
+) -> std::io::Result<Vec<ArchiveEntry>> {
+    let mut entries = Vec::new();
+    let mut push = |path: &Path, metadata: std::fs::Metadata| {
+        let name = archive_name_for(path, root);
+        // The root itself has no name; its children are still added
+        if !name.is_empty() {
+            entries.push(ArchiveEntry {
+                path: path.to_path_buf(),
+                name,
+                metadata,
+            });
+        }
+    };
 
     for file_path in paths {
+        if !visible(file_path) {
+            continue;
+        }
+
         let metadata = match std::fs::metadata(file_path) {
             Ok(metadata) => metadata,
             Err(_) => continue,
         };
 
-        let archive_path = archive_path_for(file_path);
-
         if metadata.is_file() {
-            tar.append_path_with_name(file_path, archive_path)?;
+            push(file_path, metadata);
         } else if metadata.is_dir() {
-            tar.append_dir_all(archive_path, file_path)?;
+            // Walk manually instead of append_dir_all so hidden entries are skipped
+            for entry in WalkDir::new(file_path)
+                .follow_links(true)
+                .into_iter()
+                .filter_entry(|entry| !is_internal(entry.path()) && visible(entry.path()))
+                .filter_map(Result::ok)
+            {
+                let file_type = entry.file_type();
+                if file_type.is_dir() || file_type.is_file() {
+                    push(entry.path(), entry.metadata()?);
+                }
+            }
         }
     }
 
-    let mut output = tar.into_inner()?;
-    output.flush()?;
+    Ok(entries)
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
 
-    Ok(())
+#[cfg(not(unix))]
+fn file_mode(metadata: &std::fs::Metadata) -> u32 {
+    if metadata.is_dir() {
+        0o040755
+    } else {
+        0o100644
+    }
 }
 
-fn archive_path_for(file_path: &Path) -> PathBuf {
-    PathBuf::from(api_path(file_path))
+fn archive_name_for(file_path: &Path, root: &Path) -> String {
+    let relative = file_path.strip_prefix(root).unwrap_or(file_path);
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
 }
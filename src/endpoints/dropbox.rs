@@ -0,0 +1,333 @@
+use super::capabilities::read_only_response;
+use super::upload::{
+    get_upload_semaphore, receive_upload, ExclusiveUpload, UploadOptions, UploadStatus,
+    UploadSummary,
+};
+use crate::acl::{self, Permission};
+use crate::auth::{self, Identity};
+use crate::store::Store;
+use crate::types::{api_path, data_dir, data_path, now_millis, read_only};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::sync::OnceLock;
+use tokio::fs;
+use warp::http::StatusCode;
+use warp::Reply;
+
+const UPLOAD_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+ <head>
+  <meta charset="UTF-8" />
+  <meta name="viewport" content="width=device-width, initial-scale=1.0" />
+  <title>Upload files</title>
+  <style>
+   body { font-family: monospace; max-width: 40em; margin: 2em auto; padding: 0 1em; }
+   input, button { font-family: monospace; margin: 0.5em 0; }
+  </style>
+ </head>
+ <body>
+  <h1>Upload files</h1>
+  <form id="form">
+   <input type="file" name="file" multiple required />
+   <br />
+   <button type="submit">upload</button>
+  </form>
+  <p id="status"></p>
+  <script>
+   const form = document.getElementById("form");
+   const status = document.getElementById("status");
+   form.addEventListener("submit", (event) => {
+     event.preventDefault();
+     const request = new XMLHttpRequest();
+     request.open("POST", window.location.pathname);
+     request.upload.onprogress = (e) => {
+       if (e.lengthComputable) {
+         status.textContent = `uploading: ${Math.floor((e.loaded / e.total) * 100)}%`;
+       }
+     };
+     request.onload = () => {
+       let message = request.responseText;
+       try {
+         message = JSON.parse(message);
+         message = message.message ?? message;
+       } catch {}
+       status.textContent = message;
+       if (request.status === 200) {
+         form.reset();
+       }
+     };
+     request.onerror = () => {
+       status.textContent = "upload failed";
+     };
+     request.send(new FormData(form));
+   });
+  </script>
+ </body>
+</html>
+"#;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DropBox {
+    pub token: String,
+    pub path: String,
+    pub owner: String,
+    pub created: u64,
+    pub expires: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<u64>,
+    #[serde(default)]
+    pub uploaded_bytes: u64,
+    #[serde(default)]
+    pub uploaded_files: u64,
+}
+
+#[derive(Serialize)]
+struct DropBoxInfo {
+    url: String,
+    #[serde(flatten)]
+    dropbox: DropBox,
+}
+
+#[derive(Deserialize)]
+pub struct CreateDropBoxRequest {
+    pub path: String,
+    /// Lifetime in seconds; the link never expires when omitted
+    pub expires_in: Option<u64>,
+    /// Total bytes accepted over the lifetime of the link
+    pub max_bytes: Option<u64>,
+    /// Total files accepted over the lifetime of the link
+    pub max_files: Option<u64>,
+}
+
+static DROPBOXES: OnceLock<Store<DropBox>> = OnceLock::new();
+
+fn dropboxes() -> &'static Store<DropBox> {
+    DROPBOXES.get_or_init(|| Store::open("dropboxes.json"))
+}
+
+pub async fn handle_create_dropbox(
+    request: CreateDropBoxRequest,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    if read_only() {
+        return Ok(read_only_response());
+    }
+
+    let Some(target_dir) = data_path(request.path.trim()) else {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    };
+
+    if !acl::allowed(&identity, &target_dir, Permission::Write) {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    }
+
+    if !fs::metadata(&target_dir)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir())
+    {
+        return Ok(json_response("Folder not found", StatusCode::NOT_FOUND));
+    }
+
+    let created = now_millis();
+    let dropbox = DropBox {
+        token: auth::random_token(),
+        path: api_path(&target_dir),
+        owner: identity.name,
+        created,
+        expires: request
+            .expires_in
+            .map(|seconds| created.saturating_add(seconds.saturating_mul(1000))),
+        max_bytes: request.max_bytes,
+        max_files: request.max_files,
+        uploaded_bytes: 0,
+        uploaded_files: 0,
+    };
+
+    let info = dropbox_info(dropbox.clone());
+    dropboxes().update(|items| items.push(dropbox));
+
+    Ok(warp::reply::with_status(warp::reply::json(&info), StatusCode::CREATED).into_response())
+}
+
+pub async fn handle_list_dropboxes(
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    let is_admin = acl::allowed(&identity, data_dir(), Permission::Admin);
+    let items: Vec<DropBoxInfo> = dropboxes().read(|items| {
+        items
+            .iter()
+            .filter(|dropbox| is_admin || dropbox.owner == identity.name)
+            .cloned()
+            .map(dropbox_info)
+            .collect()
+    });
+
+    Ok(warp::reply::json(&items).into_response())
+}
+
+pub async fn handle_revoke_dropbox(
+    token: String,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    let is_admin = acl::allowed(&identity, data_dir(), Permission::Admin);
+    let removed = dropboxes().update(|items| {
+        let before = items.len();
+        items.retain(|dropbox| {
+            dropbox.token != token || !(is_admin || dropbox.owner == identity.name)
+        });
+        before != items.len()
+    });
+
+    if removed {
+        Ok(json_response("Upload link revoked", StatusCode::OK))
+    } else {
+        Ok(json_response(
+            "Upload link not found",
+            StatusCode::NOT_FOUND,
+        ))
+    }
+}
+
+pub async fn handle_dropbox_page(token: String) -> Result<warp::reply::Response, Infallible> {
+    if let Err((status, message)) = find_dropbox(&token) {
+        return Ok(warp::reply::with_status(message, status).into_response());
+    }
+
+    Ok(warp::reply::html(UPLOAD_PAGE).into_response())
+}
+
+pub async fn handle_dropbox_upload(
+    token: String,
+    mut form: warp::multipart::FormData,
+) -> Result<warp::reply::Response, Infallible> {
+    if read_only() {
+        return Ok(read_only_response());
+    }
+
+    // One upload per link at a time, so caps are never exceeded concurrently
+    let Some(_exclusive) = ExclusiveUpload::acquire(&format!("dropbox:{}", token)) else {
+        return Ok(json_response(
+            "Another upload to this link is in progress",
+            StatusCode::CONFLICT,
+        ));
+    };
+
+    let dropbox = match find_dropbox(&token) {
+        Ok(dropbox) => dropbox,
+        Err((status, message)) => return Ok(json_response(message, status)),
+    };
+
+    // Uploads never grant more than their owner can currently write
+    let owner = Identity {
+        name: dropbox.owner.clone(),
+    };
+    let Some(target_dir) = data_path(&dropbox.path) else {
+        return Ok(json_response(
+            "Upload link not found",
+            StatusCode::NOT_FOUND,
+        ));
+    };
+    if !acl::allowed(&owner, &target_dir, Permission::Write) {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    }
+
+    let _permit = get_upload_semaphore().acquire().await.unwrap();
+
+    let options = UploadOptions {
+        max_files: dropbox
+            .max_files
+            .map(|max| max.saturating_sub(dropbox.uploaded_files)),
+        max_bytes: dropbox
+            .max_bytes
+            .map(|max| max.saturating_sub(dropbox.uploaded_bytes)),
+        ..UploadOptions::default()
+    };
+    let mut summary = UploadSummary::default();
+    let result = receive_upload(&mut form, &target_dir, &owner, &options, &mut summary).await;
+
+    dropboxes().update(|items| {
+        if let Some(item) = items.iter_mut().find(|item| item.token == token) {
+            item.uploaded_files += summary.files;
+            item.uploaded_bytes += summary.bytes;
+        }
+    });
+
+    Ok(upload_response(result, summary))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum DropBoxStatus {
+    Accepted,
+    Rejected,
+}
+
+#[derive(Serialize)]
+struct DropBoxResult {
+    name: String,
+    status: DropBoxStatus,
+}
+
+#[derive(Serialize)]
+struct DropBoxResponse {
+    message: String,
+    files: Vec<DropBoxResult>,
+}
+
+/// Link holders only learn whether each file was taken, never where it was
+/// stored or what else the folder holds.
+fn upload_response(
+    result: Result<(), (StatusCode, String)>,
+    summary: UploadSummary,
+) -> warp::reply::Response {
+    let (status, message) = result.err().unwrap_or_else(|| {
+        (
+            StatusCode::OK,
+            format!("Successfully uploaded {} file(s)", summary.files),
+        )
+    });
+    let files = summary
+        .results
+        .into_iter()
+        .map(|result| DropBoxResult {
+            name: result.name,
+            status: match result.status {
+                UploadStatus::Created | UploadStatus::Renamed | UploadStatus::Overwritten => {
+                    DropBoxStatus::Accepted
+                }
+                UploadStatus::Skipped | UploadStatus::Conflict => DropBoxStatus::Rejected,
+            },
+        })
+        .collect();
+
+    let response = DropBoxResponse { message, files };
+    warp::reply::with_status(warp::reply::json(&response), status).into_response()
+}
+
+fn find_dropbox(token: &str) -> Result<DropBox, (StatusCode, &'static str)> {
+    let Some(dropbox) =
+        dropboxes().read(|items| items.iter().find(|item| item.token == token).cloned())
+    else {
+        return Err((StatusCode::NOT_FOUND, "Upload link not found"));
+    };
+
+    if dropbox
+        .expires
+        .is_some_and(|expires| now_millis() > expires)
+    {
+        return Err((StatusCode::GONE, "Upload link expired"));
+    }
+
+    Ok(dropbox)
+}
+
+fn dropbox_info(dropbox: DropBox) -> DropBoxInfo {
+    DropBoxInfo {
+        url: format!("/u/{}", dropbox.token),
+        dropbox,
+    }
+}
+
+fn json_response(message: &str, status: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&message), status).into_response()
+}
@@ -0,0 +1,195 @@
+//! `Accept-Encoding` negotiation and gzip, brotli and zstd compression of
+//! text-like responses.
+
+use brotli::enc::BrotliEncoderParams;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{self, Read, Write};
+use warp::http::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use warp::http::{HeaderMap, HeaderValue, StatusCode};
+use warp::hyper::{self, Body};
+
+/// Bodies smaller than this are sent as they are; the framing would eat
+/// most of the gain
+pub(crate) const MIN_COMPRESS_SIZE: usize = 1024;
+const GZIP_LEVEL: u32 = 6;
+const BROTLI_QUALITY: i32 = 5;
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Zstd,
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// Preferred first when the client weighs several equally
+    pub(crate) const ALL: [Self; 3] = [Self::Zstd, Self::Brotli, Self::Gzip];
+
+    /// The `Content-Encoding` token
+    pub(crate) fn token(self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    /// Suffix of the files `build.rs` precompresses the UI into
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Self::Zstd => "zst",
+            Self::Brotli => "br",
+            Self::Gzip => "gz",
+        }
+    }
+}
+
+/// Picks the encoding with the highest `q` value the client accepts among
+/// `offered`, or `None` to send the body as is.
+pub(crate) fn negotiate(headers: &HeaderMap, offered: &[Encoding]) -> Option<Encoding> {
+    let accept = headers
+        .get_all("accept-encoding")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let weight = |token: &str| -> Option<f32> {
+        let mut wildcard = None;
+        for item in accept.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let q = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())
+                .unwrap_or(0.0);
+            if name.eq_ignore_ascii_case(token) {
+                return Some(q);
+            }
+            if name == "*" {
+                wildcard = Some(q);
+            }
+        }
+        wildcard
+    };
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in Encoding::ALL {
+        if !offered.contains(&encoding) {
+            continue;
+        }
+        let Some(q) = weight(encoding.token()).filter(|q| *q > 0.0) else {
+            continue;
+        };
+        if best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Text, JSON, JavaScript, XML and SVG shrink well; images, video, audio and
+/// archives are compressed already.
+pub(crate) fn compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "application/x-ndjson"
+                | "image/svg+xml"
+        )
+}
+
+/// Compresses everything `reader` yields into `writer`.
+pub(crate) fn encode(
+    encoding: Encoding,
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(writer, Compression::new(GZIP_LEVEL));
+            io::copy(reader, &mut encoder)?;
+            encoder.finish()?;
+        }
+        Encoding::Brotli => {
+            let params = BrotliEncoderParams {
+                quality: BROTLI_QUALITY,
+                ..Default::default()
+            };
+            brotli::BrotliCompress(&mut &mut *reader, &mut &mut *writer, &params)?;
+        }
+        Encoding::Zstd => zstd::stream::copy_encode(reader, writer, ZSTD_LEVEL)?,
+    }
+    Ok(())
+}
+
+/// Compresses a response whose body was built in memory, such as a JSON
+/// listing, when its type is compressible and the client accepts it.
+pub(crate) async fn compress_response(
+    headers: &HeaderMap,
+    response: warp::reply::Response,
+) -> warp::reply::Response {
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if response.status() != StatusCode::OK
+        || response.headers().contains_key(CONTENT_ENCODING)
+        || !compressible(content_type)
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+
+    let Some(encoding) = negotiate(headers, &Encoding::ALL) else {
+        return warp::reply::Response::from_parts(parts, body);
+    };
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            return warp::reply::Response::from_parts(parts, Body::empty());
+        }
+    };
+    if bytes.len() < MIN_COMPRESS_SIZE {
+        return warp::reply::Response::from_parts(parts, Body::from(bytes));
+    }
+
+    let encoded = tokio::task::spawn_blocking({
+        let bytes = bytes.clone();
+        move || {
+            let mut encoded = Vec::new();
+            encode(encoding, &mut bytes.as_ref(), &mut encoded).map(|()| encoded)
+        }
+    })
+    .await;
+    match encoded {
+        Ok(Ok(encoded)) => {
+            parts
+                .headers
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.token()));
+            parts.headers.insert(CONTENT_LENGTH, encoded.len().into());
+            warp::reply::Response::from_parts(parts, Body::from(encoded))
+        }
+        _ => warp::reply::Response::from_parts(parts, Body::from(bytes)),
+    }
+}
@@ -0,0 +1,250 @@
+use super::jobs::{accepted_response, JobHandle, JobKind};
+use super::upload::{PartialFile, UploadStatus};
+use crate::acl::{self, Permission};
+use crate::archive::{self, ArchiveKind, EntryType};
+use crate::auth::Identity;
+use crate::types::{api_path, data_path, ConflictPolicy};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::runtime::Handle;
+use warp::http::StatusCode;
+use warp::Reply;
+
+const EXTRACT_BUFFER_SIZE: usize = 64 * 1024;
+const DEFAULT_EXTRACT_MAX_GB: u64 = 64;
+
+#[derive(Deserialize)]
+pub struct ExtractRequest {
+    pub path: String,
+    /// Folder to extract into; defaults to one named after the archive, next to it
+    pub target: Option<String>,
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+}
+
+pub async fn handle_extract(
+    request: ExtractRequest,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    let Some(archive_path) = data_path(request.path.trim()) else {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    };
+
+    if !acl::allowed(&identity, &archive_path, Permission::Read) {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    }
+
+    let file_name = archive_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let Some((kind, stem)) = ArchiveKind::split_name(file_name) else {
+        return Ok(json_response(
+            "Unsupported archive format",
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+
+    let archive_size = match fs::metadata(&archive_path).await {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => return Ok(json_response("File not found", StatusCode::NOT_FOUND)),
+    };
+
+    let target = match request.target.as_deref().map(str::trim) {
+        Some(target) if !target.is_empty() => target.to_string(),
+        _ => {
+            let stem = if stem.is_empty() { "extracted" } else { stem };
+            api_path(&archive_path.with_file_name(stem))
+        }
+    };
+    let Some(target_dir) = data_path(&target) else {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    };
+
+    if !acl::allowed(&identity, &target_dir, Permission::Write) {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    }
+
+    let job = JobHandle::start(
+        JobKind::Extract,
+        &identity,
+        api_path(&archive_path),
+        api_path(&target_dir),
+    );
+    job.update(|job| job.bytes_total = archive_size);
+
+    tokio::task::spawn_blocking({
+        let job = job.clone();
+        move || {
+            let result = extract_archive(
+                &archive_path,
+                kind,
+                &target_dir,
+                request.on_conflict,
+                &identity,
+                &job,
+            );
+            job.finish(result.map_err(|e| e.to_string()));
+        }
+    });
+
+    Ok(accepted_response(&job))
+}
+
+/// Writes every file and folder of the archive below `target_dir`. Entries
+/// that would land outside it are skipped, as are links and special files.
+/// Fails once the extracted files add up to more than `EXTRACT_MAX_GB`
+/// (default 64), so a decompression bomb cannot fill the disk.
+fn extract_archive(
+    archive_path: &Path,
+    kind: ArchiveKind,
+    target_dir: &Path,
+    on_conflict: ConflictPolicy,
+    identity: &Identity,
+    job: &JobHandle,
+) -> std::io::Result<()> {
+    // Runs on a blocking thread, so async file helpers are driven with block_on
+    let runtime = Handle::current();
+    std::fs::create_dir_all(target_dir)?;
+    let target = api_path(target_dir);
+
+    let position = Arc::new(AtomicU64::new(0));
+    let reader = BufReader::with_capacity(
+        EXTRACT_BUFFER_SIZE,
+        PositionReader {
+            inner: std::fs::File::open(archive_path)?,
+            position: position.clone(),
+        },
+    );
+    let report_progress = || {
+        let done = position.load(Ordering::Relaxed);
+        job.update(|job| job.bytes_done = done);
+    };
+    let mut remaining = std::env::var("EXTRACT_MAX_GB")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_EXTRACT_MAX_GB)
+        * 1024
+        * 1024
+        * 1024;
+
+    archive::read_entries(reader, kind, |raw_name, entry_type, contents| {
+        // Entries like `./` stand for the archive root, which is the target itself
+        if Path::new(raw_name)
+            .components()
+            .all(|component| component == Component::CurDir)
+        {
+            return Ok(());
+        }
+
+        // Same rules as request paths, and the result has to stay inside the target
+        let Some(entry_path) = archive::member_name(Path::new(raw_name))
+            .and_then(|name| data_path(&format!("{}/{}", target, name)))
+            .filter(|path| path.starts_with(target_dir) && path != target_dir)
+        else {
+            job.skip(format!("{}: outside the target folder", raw_name));
+            return Ok(());
+        };
+
+        if matches!(entry_type, EntryType::Other) {
+            job.skip(format!("{}: not a regular file or folder", raw_name));
+            return Ok(());
+        }
+
+        if !acl::allowed(identity, &entry_path, Permission::Write) {
+            job.skip(format!("{}: access denied", raw_name));
+            return Ok(());
+        }
+
+        if matches!(entry_type, EntryType::Dir) {
+            std::fs::create_dir_all(&entry_path)?;
+        } else {
+            let (Some(dir), Some(filename)) = (
+                entry_path.parent(),
+                entry_path.file_name().and_then(|name| name.to_str()),
+            ) else {
+                return Ok(());
+            };
+            std::fs::create_dir_all(dir)?;
+
+            let (file, partial) = runtime.block_on(PartialFile::create(dir))?;
+            let mut file = runtime.block_on(file.into_std());
+            copy_with_progress(contents, &mut file, &mut remaining, &report_progress)?;
+            file.sync_all()?;
+            drop(file);
+
+            let (status, _) = runtime.block_on(partial.place(dir, filename, on_conflict))?;
+            match status {
+                UploadStatus::Conflict => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        format!("{} already exists", api_path(&entry_path)),
+                    ));
+                }
+                UploadStatus::Skipped => {
+                    job.skip(format!("{}: already exists", raw_name));
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+
+        job.update(|job| job.entries += 1);
+        report_progress();
+        Ok(())
+    })
+}
+
+/// Copies `reader` to `writer`, taking what it writes out of `remaining`.
+fn copy_with_progress(
+    reader: &mut dyn Read,
+    writer: &mut impl Write,
+    remaining: &mut u64,
+    report_progress: &impl Fn(),
+) -> std::io::Result<()> {
+    let mut buffer = vec![0u8; EXTRACT_BUFFER_SIZE];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        *remaining = remaining.checked_sub(read as u64).ok_or_else(|| {
+            std::io::Error::other("archive expands beyond the extraction size limit")
+        })?;
+        writer.write_all(&buffer[..read])?;
+        report_progress();
+    }
+}
+
+/// Tracks how far into the archive file reading has got, for progress.
+struct PositionReader<R> {
+    inner: R,
+    position: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for PositionReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for PositionReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = self.inner.seek(pos)?;
+        self.position.store(position, Ordering::Relaxed);
+        Ok(position)
+    }
+}
+
+fn json_response(message: &str, status: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&message), status).into_response()
+}
@@ -1,13 +1,23 @@
-use crate::types::data_path;
+use super::conditional::{Precondition, Validators};
+use super::download_bulk::blocking_body;
+use super::encoding::{self, compress_response, Encoding};
+use super::thumb::get_render_semaphore;
+use crate::acl::{self, Permission};
+use crate::archive::{self, ArchiveKind, MemberData};
+use crate::auth::Identity;
+use crate::thumbnail;
+use crate::types::{data_path, is_internal, ResizeQuery};
 use mime_guess::from_path;
 use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::fmt::Write as _;
-use std::path::Path;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
 use tokio::fs;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_util::io::ReaderStream;
-use warp::http::HeaderMap;
+use warp::http::{HeaderMap, HeaderValue};
 use warp::hyper::Body;
 use warp::{http::StatusCode, Reply};
 
@@ -23,6 +33,9 @@ const PATH_SEGMENT: &AsciiSet = &CONTROLS
     .add(b'{')
     .add(b'}');
 
+/// Ranges one request may ask for before its `Range` header is ignored
+const MAX_RANGES: usize = 64;
+
 struct DirectoryItem {
     name: String,
     sort_key: String,
@@ -31,8 +44,12 @@ struct DirectoryItem {
 
 pub async fn handle_file_server(
     path: warp::path::Tail,
+    head: bool,
+    query: HashMap<String, String>,
     headers: HeaderMap,
+    identity: Identity,
 ) -> Result<impl warp::Reply, Infallible> {
+    let resize = ResizeQuery::from_query(&query);
     let requested_path = path.as_str();
     let decoded_path = percent_decode_str(requested_path).decode_utf8_lossy();
 
@@ -45,107 +62,207 @@ pub async fn handle_file_server(
     let metadata = match fs::metadata(&file_path).await {
         Ok(metadata) => metadata,
         Err(_) => {
-            return Ok(warp::reply::with_status("Not found", StatusCode::NOT_FOUND).into_response());
+            return serve_archive_path(file_path, requested_path, head, &headers, &identity).await
         }
     };
 
     if metadata.is_dir() {
-        serve_directory(&file_path, requested_path).await
+        if !acl::visible(&identity, &file_path) {
+            return Ok(
+                warp::reply::with_status("Access denied", StatusCode::FORBIDDEN).into_response(),
+            );
+        }
+        serve_directory(&file_path, requested_path, &headers, |path| {
+            acl::visible(&identity, path)
+        })
+        .await
     } else {
-        serve_file(&file_path, &headers, metadata.len()).await
+        if !acl::allowed(&identity, &file_path, Permission::Read) {
+            return Ok(
+                warp::reply::with_status("Access denied", StatusCode::FORBIDDEN).into_response(),
+            );
+        }
+        serve_file(&file_path, &headers, metadata.len(), &resize, head).await
     }
 }
 
-async fn serve_file(
-    file_path: &Path,
+/// Serves a folder listing or a member of an archive, for paths like
+/// `backup.zip/dir/` or `backup.zip/dir/a.txt`.
+async fn serve_archive_path(
+    file_path: PathBuf,
+    requested_path: &str,
+    head: bool,
     headers: &HeaderMap,
-    file_size: u64,
+    identity: &Identity,
 ) -> Result<warp::reply::Response, Infallible> {
-    let mime_type = from_path(file_path).first_or_octet_stream().to_string();
+    let not_found = || warp::reply::with_status("Not found", StatusCode::NOT_FOUND).into_response();
 
-    // Check for Range header
-    if let Some(range_header) = headers.get("range") {
-        if let Ok(range_str) = range_header.to_str() {
-            if let Some(range) = parse_range(range_str, file_size) {
-                return serve_file_range(file_path, range, file_size, &mime_type).await;
-            }
+    let located = tokio::task::spawn_blocking(move || archive::locate(&file_path)).await;
+    let Ok(Some((archive_path, kind, inner))) = located else {
+        return Ok(not_found());
+    };
+
+    if !acl::allowed(identity, &archive_path, Permission::Read) {
+        return Ok(
+            warp::reply::with_status("Access denied", StatusCode::FORBIDDEN).into_response(),
+        );
+    }
+
+    if !inner.is_empty() && !requested_path.ends_with('/') {
+        let member = tokio::task::spawn_blocking({
+            let archive_path = archive_path.clone();
+            let inner = inner.clone();
+            move || archive::find(&archive_path, kind, &inner)
+        })
+        .await;
+
+        if let Ok(Ok(Some((member, data)))) = member {
+            let mime_type = from_path(&member.name).first_or_octet_stream().to_string();
+            return match data {
+                MemberData::Stored { offset } => {
+                    serve_file_section(
+                        &archive_path,
+                        offset,
+                        member.size,
+                        &mime_type,
+                        headers,
+                        head,
+                    )
+                    .await
+                }
+                MemberData::Compressed { index } => {
+                    serve_compressed_member(
+                        archive_path,
+                        kind,
+                        index,
+                        member.size,
+                        &mime_type,
+                        headers,
+                        head,
+                    )
+                    .await
+                }
+            };
         }
     }
 
-    // No range request - stream entire file
-    let file = match fs::File::open(file_path).await {
-        Ok(f) => f,
+    let members =
+        tokio::task::spawn_blocking(move || archive::list(&archive_path, kind, &inner)).await;
+    let Ok(Ok(Some(members))) = members else {
+        return Ok(not_found());
+    };
+
+    let mut items: Vec<DirectoryItem> = members
+        .into_iter()
+        .map(|member| {
+            let name = member
+                .name
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            DirectoryItem {
+                sort_key: name.to_lowercase(),
+                name,
+                is_dir: member.is_dir,
+            }
+        })
+        .collect();
+    sort_directory_items(&mut items);
+
+    Ok(directory_listing_response(requested_path, &items, headers).await)
+}
+
+/// Compressed members are decompressed on the fly, so ranges are not offered.
+async fn serve_compressed_member(
+    archive_path: PathBuf,
+    kind: ArchiveKind,
+    index: usize,
+    size: u64,
+    mime_type: &str,
+    headers: &HeaderMap,
+    head: bool,
+) -> Result<warp::reply::Response, Infallible> {
+    let validators = match fs::metadata(&archive_path).await {
+        Ok(metadata) => Validators::for_file(&metadata, &format!("z{:x}", index)),
         Err(_) => {
             return Ok(
                 warp::reply::with_status("File not found", StatusCode::NOT_FOUND).into_response(),
             );
         }
     };
+    match validators.evaluate(headers) {
+        Precondition::NotModified => return Ok(validators.response(StatusCode::NOT_MODIFIED)),
+        Precondition::Failed => return Ok(validators.response(StatusCode::PRECONDITION_FAILED)),
+        Precondition::Proceed { .. } => {}
+    }
 
-    let stream = ReaderStream::new(file);
-    let body = Body::wrap_stream(stream);
+    let body = if head {
+        Body::empty()
+    } else {
+        blocking_body(move |writer| archive::copy_member(&archive_path, kind, index, writer))
+    };
 
-    Ok(warp::http::Response::builder()
+    Ok(validators
+        .apply(warp::http::Response::builder())
         .status(StatusCode::OK)
         .header("content-type", mime_type)
-        .header("accept-ranges", "bytes")
-        .header("content-length", file_size.to_string())
+        .header("accept-ranges", "none")
+        .header("content-length", size.to_string())
         .body(body)
         .unwrap())
 }
 
-fn parse_range(range_str: &str, file_size: u64) -> Option<(u64, u64)> {
-    // Range header format: "bytes=start-end" or "bytes=start-" or "bytes=-suffix"
-    let range_str = range_str.strip_prefix("bytes=")?;
-
-    // We only support single ranges, not multiple ranges
-    if range_str.contains(',') {
-        return None;
-    }
-
-    let (start_str, end_str) = range_str.split_once('-')?;
-
-    match (start_str.parse::<u64>(), end_str.parse::<u64>()) {
-        (Ok(start), Ok(end)) => {
-            // "bytes=start-end"
-            if start <= end && start < file_size {
-                let end = end.min(file_size - 1);
-                Some((start, end))
-            } else {
-                None
-            }
-        }
-        (Ok(start), Err(_)) => {
-            // "bytes=start-" (from start to end of file)
-            if start < file_size {
-                Some((start, file_size - 1))
-            } else {
-                None
-            }
-        }
-        (Err(_), Ok(suffix)) => {
-            // "bytes=-suffix" (last suffix bytes)
-            if suffix > 0 && suffix <= file_size {
-                Some((file_size - suffix, file_size - 1))
-            } else {
-                None
-            }
+/// Serves a file, or for images with resize options a cached rendition of it.
+/// Anything that cannot be resized is served unchanged.
+pub(crate) async fn serve_file(
+    file_path: &Path,
+    headers: &HeaderMap,
+    file_size: u64,
+    resize: &ResizeQuery,
+    head: bool,
+) -> Result<warp::reply::Response, Infallible> {
+    let mime_type = from_path(file_path).first_or_octet_stream().to_string();
+    if !resize.is_empty() && mime_type.starts_with("image/") {
+        if let Some((path, content_type, size)) = render_resized(file_path, resize).await {
+            return serve_file_section(&path, 0, size, content_type, headers, head).await;
         }
-        _ => None,
     }
+    serve_file_section(file_path, 0, file_size, &mime_type, headers, head).await
+}
+
+async fn render_resized(
+    file_path: &Path,
+    resize: &ResizeQuery,
+) -> Option<(PathBuf, &'static str, u64)> {
+    let metadata = fs::metadata(file_path).await.ok()?;
+    let resize = thumbnail::snap_resize(resize);
+    let key = thumbnail::cache_key(file_path, &metadata, &thumbnail::resize_variant(&resize));
+
+    let _permit = get_render_semaphore().acquire().await.ok()?;
+    let source = file_path.to_path_buf();
+    let rendition = tokio::task::spawn_blocking(move || thumbnail::resized(&source, &key, &resize))
+        .await
+        .ok()?
+        .ok()?;
+
+    let size = fs::metadata(&rendition.path).await.ok()?.len();
+    Some((rendition.path, rendition.content_type, size))
 }
 
-async fn serve_file_range(
+/// Serves the `file_size` bytes at `offset` in `file_path` as one file. For
+/// HEAD requests only the metadata is read, and the body is left empty.
+pub(crate) async fn serve_file_section(
     file_path: &Path,
-    range: (u64, u64),
+    offset: u64,
     file_size: u64,
     mime_type: &str,
+    headers: &HeaderMap,
+    head: bool,
 ) -> Result<warp::reply::Response, Infallible> {
-    let (start, end) = range;
-    let content_length = end - start + 1;
-
-    let mut file = match fs::File::open(file_path).await {
-        Ok(f) => f,
+    let validators = match fs::metadata(file_path).await {
+        Ok(metadata) if offset > 0 => Validators::for_file(&metadata, &format!("{:x}", offset)),
+        Ok(metadata) => Validators::for_file(&metadata, ""),
         Err(_) => {
             return Ok(
                 warp::reply::with_status("File not found", StatusCode::NOT_FOUND).into_response(),
@@ -153,33 +270,315 @@ async fn serve_file_range(
         }
     };
 
-    // Seek to start position
-    if let Err(_) = file.seek(std::io::SeekFrom::Start(start)).await {
-        return Ok(
+    // Text is compressed on the fly, but ranges always count raw bytes
+    let compressible = encoding::compressible(mime_type);
+    let encoding = if compressible
+        && file_size >= encoding::MIN_COMPRESS_SIZE as u64
+        && !headers.contains_key("range")
+    {
+        encoding::negotiate(headers, &Encoding::ALL)
+    } else {
+        None
+    };
+    let validators = match encoding {
+        Some(encoding) => validators.encoded(encoding.token()),
+        None => validators,
+    };
+
+    let mut response = match validators.evaluate(headers) {
+        Precondition::NotModified => validators.response(StatusCode::NOT_MODIFIED),
+        Precondition::Failed => validators.response(StatusCode::PRECONDITION_FAILED),
+        Precondition::Proceed { range } => {
+            let ranges = headers
+                .get("range")
+                .filter(|_| range)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| parse_ranges(value, file_size));
+            let served = match ranges {
+                Some(ranges) => {
+                    serve_ranges(
+                        file_path,
+                        offset,
+                        ranges,
+                        file_size,
+                        mime_type,
+                        &validators,
+                        head,
+                    )
+                    .await
+                }
+                None => {
+                    serve_whole(
+                        file_path,
+                        offset,
+                        file_size,
+                        mime_type,
+                        &validators,
+                        encoding,
+                        head,
+                    )
+                    .await
+                }
+            };
+            match served {
+                Ok(response) | Err(response) => response,
+            }
+        }
+    };
+
+    if compressible {
+        response
+            .headers_mut()
+            .insert("vary", HeaderValue::from_static("accept-encoding"));
+    }
+    Ok(response)
+}
+
+/// The whole section, compressed with `encoding` if one was negotiated.
+async fn serve_whole(
+    file_path: &Path,
+    offset: u64,
+    file_size: u64,
+    mime_type: &str,
+    validators: &Validators,
+    encoding: Option<Encoding>,
+    head: bool,
+) -> Result<warp::reply::Response, warp::reply::Response> {
+    let builder = validators
+        .apply(warp::http::Response::builder())
+        .status(StatusCode::OK)
+        .header("content-type", mime_type)
+        .header("accept-ranges", "bytes");
+
+    let Some(encoding) = encoding else {
+        let body = section_body(file_path, offset, file_size, head).await?;
+        return Ok(builder
+            .header("content-length", file_size.to_string())
+            .body(body)
+            .unwrap());
+    };
+
+    // The compressed length is only known once it has been sent
+    let body = if head {
+        Body::empty()
+    } else {
+        let file = fs::File::open(file_path).await.map_err(|_| {
+            warp::reply::with_status("File not found", StatusCode::NOT_FOUND).into_response()
+        })?;
+        let mut file = file.into_std().await;
+        blocking_body(move |writer| {
+            file.seek(std::io::SeekFrom::Start(offset))?;
+            encoding::encode(encoding, &mut file.take(file_size), writer)
+        })
+    };
+    Ok(builder
+        .header("content-encoding", encoding.token())
+        .body(body)
+        .unwrap())
+}
+
+/// One range as a plain partial response, several as `multipart/byteranges`,
+/// and none that fit as `416 Range Not Satisfiable`.
+async fn serve_ranges(
+    file_path: &Path,
+    offset: u64,
+    ranges: Vec<(u64, u64)>,
+    file_size: u64,
+    mime_type: &str,
+    validators: &Validators,
+    head: bool,
+) -> Result<warp::reply::Response, warp::reply::Response> {
+    match ranges.as_slice() {
+        [] => Ok(validators
+            .apply(warp::http::Response::builder())
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header("accept-ranges", "bytes")
+            .header("content-range", format!("bytes */{}", file_size))
+            .body(Body::empty())
+            .unwrap()),
+        &[(start, end)] => {
+            let body = section_body(file_path, offset + start, end - start + 1, head).await?;
+            Ok(validators
+                .apply(warp::http::Response::builder())
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header("content-type", mime_type)
+                .header("accept-ranges", "bytes")
+                .header(
+                    "content-range",
+                    format!("bytes {}-{}/{}", start, end, file_size),
+                )
+                .header("content-length", (end - start + 1).to_string())
+                .body(body)
+                .unwrap())
+        }
+        _ => {
+            serve_multipart(
+                file_path, offset, ranges, file_size, mime_type, validators, head,
+            )
+            .await
+        }
+    }
+}
+
+/// Streams `length` bytes from `start` in `file_path`, or nothing for HEAD
+/// requests.
+async fn section_body(
+    file_path: &Path,
+    start: u64,
+    length: u64,
+    head: bool,
+) -> Result<Body, warp::reply::Response> {
+    if head {
+        return Ok(Body::empty());
+    }
+
+    let mut file = fs::File::open(file_path).await.map_err(|_| {
+        warp::reply::with_status("File not found", StatusCode::NOT_FOUND).into_response()
+    })?;
+    if start > 0 && file.seek(std::io::SeekFrom::Start(start)).await.is_err() {
+        return Err(
             warp::reply::with_status("Seek failed", StatusCode::INTERNAL_SERVER_ERROR)
                 .into_response(),
         );
     }
 
-    let limited_reader = file.take(content_length);
-    let stream = ReaderStream::new(limited_reader);
-    let body = Body::wrap_stream(stream);
+    Ok(Body::wrap_stream(ReaderStream::new(file.take(length))))
+}
 
-    let content_range = format!("bytes {}-{}/{}", start, end, file_size);
+/// Parses a `Range` header into sorted ranges of inclusive byte positions,
+/// with overlapping and adjacent ones merged. `None` means the header is
+/// malformed (or asks for too many pieces) and should be ignored; an empty
+/// list means none of the ranges fall inside the file.
+fn parse_ranges(range_str: &str, file_size: u64) -> Option<Vec<(u64, u64)>> {
+    // "bytes=start-end", "bytes=start-" or "bytes=-suffix", comma separated
+    let range_str = range_str.trim().strip_prefix("bytes=")?;
+
+    let specs: Vec<&str> = range_str
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return None;
+    }
+
+    let mut ranges = Vec::with_capacity(specs.len());
+    for spec in specs {
+        let (start_str, end_str) = spec.split_once('-')?;
+        let range = if start_str.is_empty() {
+            // Last `suffix` bytes
+            let suffix = end_str.parse::<u64>().ok()?;
+            (suffix > 0 && file_size > 0).then(|| (file_size.saturating_sub(suffix), file_size - 1))
+        } else {
+            let start = start_str.parse::<u64>().ok()?;
+            let end = if end_str.is_empty() {
+                u64::MAX
+            } else {
+                end_str.parse::<u64>().ok()?
+            };
+            if start > end {
+                return None;
+            }
+            (start < file_size).then(|| (start, end.min(file_size - 1)))
+        };
+        ranges.extend(range);
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    Some(merged)
+}
+
+/// Several ranges as one `multipart/byteranges` body, read from disk as it is
+/// sent.
+async fn serve_multipart(
+    file_path: &Path,
+    offset: u64,
+    ranges: Vec<(u64, u64)>,
+    file_size: u64,
+    mime_type: &str,
+    validators: &Validators,
+    head: bool,
+) -> Result<warp::reply::Response, warp::reply::Response> {
+    let mut random = [0u8; 12];
+    if getrandom::getrandom(&mut random).is_err() {
+        return Err(warp::reply::with_status(
+            "Failed to create boundary",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .into_response());
+    }
+    let boundary: String = random.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    let parts: Vec<(String, (u64, u64))> = ranges
+        .into_iter()
+        .enumerate()
+        .map(|(index, (start, end))| {
+            let header = format!(
+                "{}--{}\r\ncontent-type: {}\r\ncontent-range: bytes {}-{}/{}\r\n\r\n",
+                if index == 0 { "" } else { "\r\n" },
+                boundary,
+                mime_type,
+                start,
+                end,
+                file_size
+            );
+            (header, (start, end))
+        })
+        .collect();
+    let closing = format!("\r\n--{}--\r\n", boundary);
+    let content_length = parts
+        .iter()
+        .map(|(header, (start, end))| header.len() as u64 + end - start + 1)
+        .sum::<u64>()
+        + closing.len() as u64;
+
+    let body = if head {
+        Body::empty()
+    } else {
+        let Ok(file) = fs::File::open(file_path).await else {
+            return Err(
+                warp::reply::with_status("File not found", StatusCode::NOT_FOUND).into_response(),
+            );
+        };
+        let mut file = file.into_std().await;
+        blocking_body(move |writer| {
+            for (header, (start, end)) in parts {
+                writer.write_all(header.as_bytes())?;
+                file.seek(std::io::SeekFrom::Start(offset + start))?;
+                let length = end - start + 1;
+                if std::io::copy(&mut (&file).take(length), writer)? != length {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+            writer.write_all(closing.as_bytes())
+        })
+    };
 
-    Ok(warp::http::Response::builder()
+    Ok(validators
+        .apply(warp::http::Response::builder())
         .status(StatusCode::PARTIAL_CONTENT)
-        .header("content-type", mime_type)
+        .header(
+            "content-type",
+            format!("multipart/byteranges; boundary={}", boundary),
+        )
         .header("accept-ranges", "bytes")
-        .header("content-range", content_range)
         .header("content-length", content_length.to_string())
         .body(body)
         .unwrap())
 }
 
-async fn serve_directory(
+pub(crate) async fn serve_directory(
     dir_path: &Path,
     requested_path: &str,
+    headers: &HeaderMap,
+    visible: impl Fn(&Path) -> bool,
 ) -> Result<warp::reply::Response, Infallible> {
     let mut entries = match fs::read_dir(dir_path).await {
         Ok(entries) => entries,
@@ -195,6 +594,10 @@ async fn serve_directory(
     let mut items = Vec::new();
 
     while let Ok(Some(entry)) = entries.next_entry().await {
+        if is_internal(&entry.path()) || !visible(&entry.path()) {
+            continue;
+        }
+
         if let Ok(metadata) = entry.metadata().await {
             if let Some(name) = entry.file_name().to_str() {
                 let is_dir = metadata.is_dir();
@@ -210,25 +613,37 @@ async fn serve_directory(
         }
     }
 
+    sort_directory_items(&mut items);
+    Ok(directory_listing_response(requested_path, &items, headers).await)
+}
+
+fn sort_directory_items(items: &mut [DirectoryItem]) {
     items.sort_by(|a, b| match (a.is_dir, b.is_dir) {
         (true, false) => std::cmp::Ordering::Less,
         (false, true) => std::cmp::Ordering::Greater,
         _ => a.sort_key.cmp(&b.sort_key),
     });
+}
 
+async fn directory_listing_response(
+    requested_path: &str,
+    items: &[DirectoryItem],
+    headers: &HeaderMap,
+) -> warp::reply::Response {
     let display_path = if requested_path.is_empty() {
         "/".to_string()
     } else {
         format!("/{}", requested_path.trim_start_matches('/'))
     };
-    let html = generate_directory_listing(&display_path, &items);
+    let html = generate_directory_listing(&display_path, items);
 
-    Ok(warp::reply::with_header(
+    let response = warp::reply::with_header(
         warp::reply::html(html),
         "content-type",
         "text/html; charset=utf-8",
     )
-    .into_response())
+    .into_response();
+    compress_response(headers, response).await
 }
 
 fn generate_directory_listing(path: &str, items: &[DirectoryItem]) -> String {
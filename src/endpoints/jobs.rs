@@ -0,0 +1,182 @@
+use crate::auth::{self, Identity};
+use crate::types::now_millis;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Mutex, OnceLock};
+use warp::http::StatusCode;
+use warp::Reply;
+
+/// Finished jobs stay visible this long so clients polling slowly still see the outcome.
+const FINISHED_JOB_RETENTION_MS: u64 = 60 * 60 * 1000;
+/// Skipped entries listed by name; the rest are only counted
+const MAX_SKIPPED_LISTED: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    Extract,
+    Compress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// A long-running server-side operation, polled through `/api/jobs/<id>`.
+#[derive(Clone, Serialize)]
+pub struct Job {
+    pub id: String,
+    pub kind: JobKind,
+    #[serde(skip)]
+    pub owner: String,
+    pub state: JobState,
+    /// What the job reads, relative to the data directory
+    pub source: String,
+    /// What the job writes, relative to the data directory
+    pub target: String,
+    /// Progress through the input; `bytes_total` is 0 until it is known
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    /// Files and folders written so far
+    pub entries: u64,
+    /// The first entries left out, each with the reason
+    pub skipped: Vec<String>,
+    /// All entries left out, including those not listed in `skipped`
+    pub skipped_count: u64,
+    pub error: Option<String>,
+    pub created: u64,
+    pub finished: Option<u64>,
+}
+
+static JOBS: OnceLock<Mutex<HashMap<String, Job>>> = OnceLock::new();
+
+fn jobs() -> &'static Mutex<HashMap<String, Job>> {
+    JOBS.get_or_init(Default::default)
+}
+
+/// Lets the worker running a job report on it.
+#[derive(Clone)]
+pub struct JobHandle {
+    id: String,
+}
+
+impl JobHandle {
+    /// Registers a running job and forgets jobs that finished long ago.
+    pub fn start(kind: JobKind, owner: &Identity, source: String, target: String) -> Self {
+        let now = now_millis();
+        let id = auth::random_token();
+        let job = Job {
+            id: id.clone(),
+            kind,
+            owner: owner.name.clone(),
+            state: JobState::Running,
+            source,
+            target,
+            bytes_done: 0,
+            bytes_total: 0,
+            entries: 0,
+            skipped: Vec::new(),
+            skipped_count: 0,
+            error: None,
+            created: now,
+            finished: None,
+        };
+
+        let mut jobs = jobs().lock().unwrap();
+        jobs.retain(|_, job| {
+            job.finished
+                .is_none_or(|finished| now.saturating_sub(finished) < FINISHED_JOB_RETENTION_MS)
+        });
+        jobs.insert(id.clone(), job);
+        Self { id }
+    }
+
+    pub fn snapshot(&self) -> Option<Job> {
+        jobs().lock().unwrap().get(&self.id).cloned()
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut Job)) {
+        if let Some(job) = jobs().lock().unwrap().get_mut(&self.id) {
+            f(job);
+        }
+    }
+
+    pub fn skip(&self, reason: String) {
+        self.update(|job| {
+            job.skipped_count += 1;
+            if job.skipped.len() < MAX_SKIPPED_LISTED {
+                job.skipped.push(reason);
+            }
+        });
+    }
+
+    pub fn finish(&self, result: Result<(), String>) {
+        self.update(|job| {
+            job.finished = Some(now_millis());
+            match result {
+                Ok(()) => {
+                    job.state = JobState::Completed;
+                    job.bytes_done = job.bytes_done.max(job.bytes_total);
+                }
+                Err(e) => {
+                    job.state = JobState::Failed;
+                    job.error = Some(e);
+                }
+            }
+        });
+    }
+}
+
+/// 202 response for a newly started job.
+pub(crate) fn accepted_response(job: &JobHandle) -> warp::reply::Response {
+    match job.snapshot() {
+        Some(job) => warp::reply::with_header(
+            warp::reply::with_status(warp::reply::json(&job), StatusCode::ACCEPTED),
+            "location",
+            format!("/api/jobs/{}", job.id),
+        )
+        .into_response(),
+        None => {
+            warp::reply::with_status(warp::reply::json(&"Job not found"), StatusCode::NOT_FOUND)
+                .into_response()
+        }
+    }
+}
+
+pub async fn handle_list_jobs(identity: Identity) -> Result<warp::reply::Response, Infallible> {
+    let mut own: Vec<Job> = jobs()
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|job| job.owner == identity.name)
+        .cloned()
+        .collect();
+    own.sort_by_key(|job| job.created);
+
+    Ok(warp::reply::json(&own).into_response())
+}
+
+pub async fn handle_job(
+    id: String,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    let job = jobs()
+        .lock()
+        .unwrap()
+        .get(&id)
+        .filter(|job| job.owner == identity.name)
+        .cloned();
+
+    Ok(match job {
+        Some(job) => warp::reply::json(&job).into_response(),
+        None => {
+            warp::reply::with_status(warp::reply::json(&"Job not found"), StatusCode::NOT_FOUND)
+                .into_response()
+        }
+    })
+}
@@ -1,10 +1,16 @@
-use crate::types::{api_path, data_path, ListQuery};
+use super::encoding::compress_response;
+use crate::acl::{self, Permission};
+use crate::archive::{self, ArchiveKind};
+use crate::auth::Identity;
+use crate::thumbnail;
+use crate::types::{api_path, data_path, is_internal, ListQuery};
 use percent_encoding::percent_decode_str;
 use serde::Serialize;
 use std::convert::Infallible;
+use std::path::PathBuf;
 use std::time::UNIX_EPOCH;
 use tokio::fs;
-use warp::http::StatusCode;
+use warp::http::{HeaderMap, StatusCode};
 use warp::Reply;
 
 #[derive(Serialize)]
@@ -14,9 +20,27 @@ struct ListItem {
     is_dir: bool,
     size: u64,
     modified: u64,
+    /// An archive file the UI can open like a folder
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    archive: bool,
+    /// An image or video `/api/thumb` can render a preview of
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    thumbnail: bool,
 }
 
-pub async fn handle_list(query: ListQuery) -> Result<warp::reply::Response, Infallible> {
+pub async fn handle_list(
+    query: ListQuery,
+    headers: HeaderMap,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    let response = list_directory(query, identity).await?;
+    Ok(compress_response(&headers, response).await)
+}
+
+async fn list_directory(
+    query: ListQuery,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
     let path = query.path.unwrap_or_default();
     let decoded_path = percent_decode_str(&path).decode_utf8_lossy();
     let Some(dir_path) = data_path(decoded_path.as_ref()) else {
@@ -25,19 +49,24 @@ pub async fn handle_list(query: ListQuery) -> Result<warp::reply::Response, Infa
         );
     };
 
+    if !acl::visible(&identity, &dir_path) {
+        return Ok(
+            warp::reply::with_status("Access denied", StatusCode::FORBIDDEN).into_response(),
+        );
+    }
+
     let mut entries = match fs::read_dir(&dir_path).await {
         Ok(entries) => entries,
-        Err(_) => {
-            return Ok(
-                warp::reply::with_status("Cannot read directory", StatusCode::NOT_FOUND)
-                    .into_response(),
-            );
-        }
+        Err(_) => return Ok(list_archive(dir_path, &identity).await),
     };
 
     let mut items = Vec::new();
 
     while let Ok(Some(entry)) = entries.next_entry().await {
+        if is_internal(&entry.path()) || !acl::visible(&identity, &entry.path()) {
+            continue;
+        }
+
         let metadata = match entry.metadata().await {
             Ok(metadata) => metadata,
             Err(_) => continue,
@@ -50,6 +79,8 @@ pub async fn handle_list(query: ListQuery) -> Result<warp::reply::Response, Infa
 
         items.push(ListItem {
             path: api_path(&entry.path()),
+            archive: metadata.is_file() && ArchiveKind::from_path(&entry.path()).is_some(),
+            thumbnail: metadata.is_file() && thumbnail::available(&entry.path()),
             name,
             is_dir: metadata.is_dir(),
             size: if metadata.is_file() {
@@ -61,13 +92,59 @@ pub async fn handle_list(query: ListQuery) -> Result<warp::reply::Response, Infa
         });
     }
 
+    sort_items(&mut items);
+    Ok(warp::reply::json(&items).into_response())
+}
+
+/// Lists a folder inside an archive, for paths like `backup.zip/dir`.
+async fn list_archive(dir_path: PathBuf, identity: &Identity) -> warp::reply::Response {
+    let not_found =
+        || warp::reply::with_status("Cannot read directory", StatusCode::NOT_FOUND).into_response();
+
+    let located = tokio::task::spawn_blocking(move || archive::locate(&dir_path)).await;
+    let Ok(Some((archive_path, kind, inner))) = located else {
+        return not_found();
+    };
+
+    if !acl::allowed(identity, &archive_path, Permission::Read) {
+        return warp::reply::with_status("Access denied", StatusCode::FORBIDDEN).into_response();
+    }
+
+    let archive_api_path = api_path(&archive_path);
+    let members =
+        tokio::task::spawn_blocking(move || archive::list(&archive_path, kind, &inner)).await;
+    let Ok(Ok(Some(members))) = members else {
+        return not_found();
+    };
+
+    let mut items: Vec<ListItem> = members
+        .into_iter()
+        .map(|member| ListItem {
+            name: member
+                .name
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            path: format!("{}/{}", archive_api_path, member.name),
+            is_dir: member.is_dir,
+            size: member.size,
+            modified: member.modified,
+            archive: false,
+            thumbnail: false,
+        })
+        .collect();
+
+    sort_items(&mut items);
+    warp::reply::json(&items).into_response()
+}
+
+fn sort_items(items: &mut [ListItem]) {
     items.sort_by(|a, b| match (a.is_dir, b.is_dir) {
         (true, false) => std::cmp::Ordering::Less,
         (false, true) => std::cmp::Ordering::Greater,
         _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
     });
-
-    Ok(warp::reply::json(&items).into_response())
 }
 
 fn modified_millis(metadata: &std::fs::Metadata) -> u64 {
@@ -0,0 +1,48 @@
+use crate::acl::{self, Permission};
+use crate::auth::Identity;
+use crate::metadata;
+use crate::types::{data_path, FileQuery};
+use percent_encoding::percent_decode_str;
+use std::convert::Infallible;
+use tokio::fs;
+use warp::http::StatusCode;
+use warp::Reply;
+
+pub async fn handle_meta(
+    query: FileQuery,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    let decoded_path = percent_decode_str(&query.path).decode_utf8_lossy();
+    let Some(file_path) = data_path(decoded_path.as_ref()) else {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    };
+
+    if !acl::allowed(&identity, &file_path, Permission::Read) {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    }
+
+    let metadata = match fs::metadata(&file_path).await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return Ok(json_response("File not found", StatusCode::NOT_FOUND)),
+    };
+
+    if !metadata::supported(&file_path) {
+        return Ok(json_response(
+            "No metadata for this file type",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ));
+    }
+
+    let meta = tokio::task::spawn_blocking(move || metadata::read(&file_path, &metadata)).await;
+    match meta {
+        Ok(Ok(meta)) => Ok(warp::reply::json(&meta).into_response()),
+        _ => Ok(json_response(
+            "Cannot read file metadata",
+            StatusCode::UNPROCESSABLE_ENTITY,
+        )),
+    }
+}
+
+fn json_response(message: &str, status: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&message), status).into_response()
+}
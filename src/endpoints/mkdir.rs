@@ -1,10 +1,16 @@
+use crate::acl::{self, Permission};
+use crate::auth::Identity;
 use crate::types::{data_path, FileQuery};
 use percent_encoding::percent_decode_str;
 use std::convert::Infallible;
+use std::path::Path;
 use tokio::fs;
 use warp::http::StatusCode;
 
-pub async fn handle_mkdir(query: FileQuery) -> Result<impl warp::Reply, Infallible> {
+pub async fn handle_mkdir(
+    query: FileQuery,
+    identity: Identity,
+) -> Result<impl warp::Reply, Infallible> {
     let decoded_path = percent_decode_str(&query.path).decode_utf8_lossy();
     let Some(folder_path) = data_path(decoded_path.as_ref()) else {
         return Ok(warp::reply::with_status(
@@ -13,7 +19,14 @@ pub async fn handle_mkdir(query: FileQuery) -> Result<impl warp::Reply, Infallib
         ));
     };
 
-    match fs::create_dir_all(&folder_path).await {
+    if !acl::allowed(&identity, &folder_path, Permission::Write) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"Access denied"),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+
+    match create_folder(&folder_path).await {
         Ok(_) => Ok(warp::reply::with_status(
             warp::reply::json(&"Folder created successfully"),
             StatusCode::OK,
@@ -24,3 +37,7 @@ pub async fn handle_mkdir(query: FileQuery) -> Result<impl warp::Reply, Infallib
         )),
     }
 }
+
+pub(crate) async fn create_folder(path: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(path).await
+}
@@ -1,24 +1,49 @@
+pub mod capabilities;
+pub mod compress;
+pub mod conditional;
 pub mod delete;
 pub mod download;
 pub mod download_bulk;
+pub mod dropbox;
+pub mod encoding;
+pub mod extract;
 pub mod file_server;
+pub mod jobs;
 pub mod list;
+pub mod meta;
 pub mod mkdir;
 pub mod mv;
 pub mod save;
 pub mod search;
+pub mod shares;
+pub mod thumb;
+pub mod tus;
 pub mod ui;
 pub mod upload;
+pub mod webdav;
 
 // Re-export handler functions
+pub use capabilities::{handle_capabilities, read_only_response};
+pub use compress::handle_compress;
 pub use delete::handle_delete;
 pub use download::handle_download;
 pub use download_bulk::handle_downloads;
+pub use dropbox::{
+    handle_create_dropbox, handle_dropbox_page, handle_dropbox_upload, handle_list_dropboxes,
+    handle_revoke_dropbox,
+};
+pub use extract::handle_extract;
 pub use file_server::handle_file_server;
+pub use jobs::{handle_job, handle_list_jobs};
 pub use list::handle_list;
+pub use meta::handle_meta;
 pub use mkdir::handle_mkdir;
 pub use mv::handle_mv;
 pub use save::handle_save;
 pub use search::handle_search;
+pub use shares::{handle_create_share, handle_list_shares, handle_revoke_share, handle_share};
+pub use thumb::handle_thumb;
+pub use tus::handle_tus;
 pub use ui::ui_routes;
 pub use upload::handle_upload;
+pub use webdav::handle_webdav;
@@ -1,6 +1,10 @@
+use super::delete::is_data_root;
+use crate::acl::{self, Permission};
+use crate::auth::Identity;
 use crate::types::data_path;
 use serde::Deserialize;
 use std::convert::Infallible;
+use std::path::Path;
 use tokio::fs;
 use warp::http::StatusCode;
 
@@ -10,7 +14,10 @@ pub struct MvItem {
     pub to: String,
 }
 
-pub async fn handle_mv(items: Vec<MvItem>) -> Result<impl warp::Reply, Infallible> {
+pub async fn handle_mv(
+    items: Vec<MvItem>,
+    identity: Identity,
+) -> Result<impl warp::Reply, Infallible> {
     if items.is_empty() {
         return Ok(warp::reply::with_status(
             warp::reply::json(&"No files specified"),
@@ -26,7 +33,23 @@ pub async fn handle_mv(items: Vec<MvItem>) -> Result<impl warp::Reply, Infallibl
             ));
         };
 
-        if fs::rename(&from_path, &to_path).await.is_err() {
+        if !acl::allowed(&identity, &from_path, Permission::Delete)
+            || !acl::allowed(&identity, &to_path, Permission::Write)
+        {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&"Access denied"),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+
+        if is_data_root(&from_path).await || is_data_root(&to_path).await {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&"Cannot move the root folder"),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+
+        if move_path(&from_path, &to_path).await.is_err() {
             return Ok(warp::reply::with_status(
                 warp::reply::json(&"Failed to move"),
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -39,3 +62,7 @@ pub async fn handle_mv(items: Vec<MvItem>) -> Result<impl warp::Reply, Infallibl
         StatusCode::OK,
     ))
 }
+
+pub(crate) async fn move_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::rename(from, to).await
+}
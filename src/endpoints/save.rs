@@ -1,11 +1,16 @@
+use super::upload::PartialFile;
+use crate::acl::{self, Permission};
+use crate::auth::Identity;
 use crate::types::{data_path, FileQuery};
 use std::convert::Infallible;
-use tokio::fs;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
 use warp::Reply;
 
 pub async fn handle_save(
     query: FileQuery,
     body: bytes::Bytes,
+    identity: Identity,
 ) -> Result<impl warp::Reply, Infallible> {
     let Some(file_path) = data_path(&query.path) else {
         return Ok(
@@ -14,8 +19,15 @@ pub async fn handle_save(
         );
     };
 
-    match fs::write(&file_path, body).await {
-        Ok(_) => Ok(warp::reply::with_status(
+    if !acl::allowed(&identity, &file_path, Permission::Write) {
+        return Ok(
+            warp::reply::with_status("Access denied", warp::http::StatusCode::FORBIDDEN)
+                .into_response(),
+        );
+    }
+
+    match write_atomically(&file_path, &body).await {
+        Ok(()) => Ok(warp::reply::with_status(
             "File saved successfully",
             warp::http::StatusCode::OK,
         )
@@ -27,3 +39,13 @@ pub async fn handle_save(
         .into_response()),
     }
 }
+
+/// Readers see either the old contents or the new ones, never a partial write.
+async fn write_atomically(file_path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let dir = file_path.parent().ok_or(std::io::ErrorKind::NotFound)?;
+    let (mut file, partial) = PartialFile::create(dir).await?;
+    file.write_all(contents).await?;
+    file.flush().await?;
+    drop(file);
+    partial.replace(file_path).await
+}
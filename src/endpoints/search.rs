@@ -1,11 +1,15 @@
-use crate::types::{api_path, data_path, SearchQuery};
+use super::encoding::compress_response;
+use crate::acl;
+use crate::auth::Identity;
+use crate::thumbnail;
+use crate::types::{api_path, data_path, is_internal, SearchQuery};
 use percent_encoding::percent_decode_str;
 use serde::Serialize;
 use std::cmp::Ordering;
 use std::convert::Infallible;
 use std::time::UNIX_EPOCH;
 use walkdir::WalkDir;
-use warp::http::StatusCode;
+use warp::http::{HeaderMap, StatusCode};
 use warp::Reply;
 
 const MAX_SEARCH_RESULTS: usize = 500;
@@ -17,9 +21,23 @@ struct SearchItem {
     is_dir: bool,
     size: u64,
     modified: u64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    thumbnail: bool,
 }
 
-pub async fn handle_search(query: SearchQuery) -> Result<warp::reply::Response, Infallible> {
+pub async fn handle_search(
+    query: SearchQuery,
+    headers: HeaderMap,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    let response = search_directory(query, identity).await?;
+    Ok(compress_response(&headers, response).await)
+}
+
+async fn search_directory(
+    query: SearchQuery,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
     let path = query.path.unwrap_or_default();
     let search = query.query.unwrap_or_default();
 
@@ -30,6 +48,12 @@ pub async fn handle_search(query: SearchQuery) -> Result<warp::reply::Response,
         );
     };
 
+    if !acl::visible(&identity, &dir_path) {
+        return Ok(
+            warp::reply::with_status("Access denied", StatusCode::FORBIDDEN).into_response(),
+        );
+    }
+
     let terms: Vec<String> = search
         .split_whitespace()
         .map(|term| term.to_lowercase())
@@ -54,6 +78,7 @@ pub async fn handle_search(query: SearchQuery) -> Result<warp::reply::Response,
         .min_depth(1)
         .follow_links(false)
         .into_iter()
+        .filter_entry(|entry| !is_internal(entry.path()) && acl::visible(&identity, entry.path()))
         .filter_map(Result::ok)
     {
         let path = entry.path();
@@ -87,6 +112,7 @@ pub async fn handle_search(query: SearchQuery) -> Result<warp::reply::Response,
                 .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
                 .map(|duration| duration.as_millis() as u64)
                 .unwrap_or(0),
+            thumbnail: metadata.is_file() && thumbnail::available(path),
         });
 
         if items.len() >= MAX_SEARCH_RESULTS {
@@ -0,0 +1,374 @@
+use super::download_bulk::{create_archive_response, ArchiveFormat};
+use super::file_server::{serve_directory, serve_file};
+use crate::acl::{self, Permission};
+use crate::auth::{self, Identity};
+use crate::store::Store;
+use crate::types::{api_path, data_dir, data_path, now_millis, ResizeQuery};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+use tokio::fs;
+use warp::http::{HeaderMap, StatusCode};
+use warp::Reply;
+
+const SHARE_REALM: &str = "shared link";
+/// Milliseconds during which requests from one client for one file count as
+/// a single download
+const RETRIEVAL_WINDOW: u64 = 30 * 60 * 1000;
+const RETRIEVAL_CAPACITY: usize = 4096;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Share {
+    pub token: String,
+    pub path: String,
+    pub owner: String,
+    pub created: u64,
+    pub expires: Option<u64>,
+    /// Argon2 hash of the share password, if any
+    pub password: Option<String>,
+    pub max_downloads: Option<u64>,
+    #[serde(default)]
+    pub downloads: u64,
+}
+
+#[derive(Serialize)]
+struct ShareInfo {
+    token: String,
+    url: String,
+    path: String,
+    owner: String,
+    created: u64,
+    expires: Option<u64>,
+    has_password: bool,
+    max_downloads: Option<u64>,
+    downloads: u64,
+}
+
+#[derive(Deserialize)]
+pub struct CreateShareRequest {
+    pub path: String,
+    /// Lifetime in seconds; the share never expires when omitted
+    pub expires_in: Option<u64>,
+    pub password: Option<String>,
+    pub max_downloads: Option<u64>,
+}
+
+static SHARES: OnceLock<Store<Share>> = OnceLock::new();
+
+// When each counted (share, client, file version) was last requested
+static RETRIEVALS: OnceLock<Mutex<HashMap<String, u64>>> = OnceLock::new();
+
+fn shares() -> &'static Store<Share> {
+    SHARES.get_or_init(|| Store::open("shares.json"))
+}
+
+pub async fn handle_create_share(
+    request: CreateShareRequest,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    let Some(file_path) = data_path(request.path.trim()) else {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    };
+
+    if !acl::allowed(&identity, &file_path, Permission::Read) {
+        return Ok(json_response("Access denied", StatusCode::FORBIDDEN));
+    }
+
+    let is_dir = match fs::metadata(&file_path).await {
+        Ok(metadata) => metadata.is_dir(),
+        Err(_) => return Ok(json_response("File not found", StatusCode::NOT_FOUND)),
+    };
+
+    let password = match request.password.filter(|password| !password.is_empty()) {
+        Some(password) => {
+            match tokio::task::spawn_blocking(move || auth::hash_password(&password)).await {
+                Ok(Ok(hash)) => Some(hash),
+                _ => {
+                    return Ok(json_response(
+                        "Failed to hash password",
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ));
+                }
+            }
+        }
+        None => None,
+    };
+
+    let created = now_millis();
+    let share = Share {
+        token: auth::random_token(),
+        path: api_path(&file_path),
+        owner: identity.name,
+        created,
+        expires: request
+            .expires_in
+            .map(|seconds| created.saturating_add(seconds.saturating_mul(1000))),
+        password,
+        max_downloads: request.max_downloads,
+        downloads: 0,
+    };
+
+    let info = share_info(&share, is_dir);
+    shares().update(|items| items.push(share));
+
+    Ok(warp::reply::with_status(warp::reply::json(&info), StatusCode::CREATED).into_response())
+}
+
+pub async fn handle_list_shares(identity: Identity) -> Result<warp::reply::Response, Infallible> {
+    let is_admin = acl::allowed(&identity, data_dir(), Permission::Admin);
+    let items: Vec<ShareInfo> = shares().read(|items| {
+        items
+            .iter()
+            .filter(|share| is_admin || share.owner == identity.name)
+            .map(|share| {
+                let is_dir = data_path(&share.path).is_some_and(|path| path.is_dir());
+                share_info(share, is_dir)
+            })
+            .collect()
+    });
+
+    Ok(warp::reply::json(&items).into_response())
+}
+
+pub async fn handle_revoke_share(
+    token: String,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    let is_admin = acl::allowed(&identity, data_dir(), Permission::Admin);
+    let removed = shares().update(|items| {
+        let before = items.len();
+        items.retain(|share| share.token != token || !(is_admin || share.owner == identity.name));
+        before != items.len()
+    });
+
+    if removed {
+        Ok(json_response("Share revoked", StatusCode::OK))
+    } else {
+        Ok(json_response("Share not found", StatusCode::NOT_FOUND))
+    }
+}
+
+pub async fn handle_share(
+    token: String,
+    tail: warp::path::Tail,
+    full_path: warp::path::FullPath,
+    head: bool,
+    query: HashMap<String, String>,
+    headers: HeaderMap,
+    remote: Option<SocketAddr>,
+) -> Result<warp::reply::Response, Infallible> {
+    let Some(share) = shares().read(|items| items.iter().find(|s| s.token == token).cloned())
+    else {
+        return Ok(text_response("Share not found", StatusCode::NOT_FOUND));
+    };
+
+    if share.expires.is_some_and(|expires| now_millis() > expires) {
+        return Ok(text_response("Share expired", StatusCode::GONE));
+    }
+
+    if let Some(hash) = &share.password {
+        let password = headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(auth::basic_credentials)
+            .map(|(_, password)| password);
+
+        let valid = match password {
+            Some(password) => auth::verify_password_cached(&password, hash).await,
+            None => false,
+        };
+        if !valid {
+            return Ok(auth::challenge_response(SHARE_REALM));
+        }
+    }
+
+    // Shares never grant more than their owner can currently read
+    let owner = Identity {
+        name: share.owner.clone(),
+    };
+
+    let decoded_tail = percent_decode_str(tail.as_str()).decode_utf8_lossy();
+    let Some(share_root) = data_path(&share.path) else {
+        return Ok(text_response("Share not found", StatusCode::NOT_FOUND));
+    };
+    let requested = if decoded_tail.is_empty() {
+        share.path.clone()
+    } else {
+        format!("{}/{}", share.path, decoded_tail)
+    };
+    let Some(file_path) = data_path(&requested) else {
+        return Ok(text_response("Access denied", StatusCode::FORBIDDEN));
+    };
+
+    if !acl::visible(&owner, &file_path) {
+        return Ok(text_response("Access denied", StatusCode::FORBIDDEN));
+    }
+
+    let metadata = match fs::metadata(&file_path).await {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(text_response("Not found", StatusCode::NOT_FOUND)),
+    };
+
+    if metadata.is_dir() {
+        if let Some(format) = query
+            .get("download")
+            .and_then(|format| ArchiveFormat::parse(format))
+        {
+            if !head && !register_download(&share.token) {
+                return Ok(text_response("Download limit reached", StatusCode::GONE));
+            }
+
+            let root = share_root.parent().unwrap_or(data_dir()).to_path_buf();
+            return create_archive_response(
+                vec![api_path(&file_path)],
+                root,
+                format,
+                query.get("level").and_then(|level| level.parse().ok()),
+                head,
+                move |path| acl::visible(&owner, path),
+            )
+            .await;
+        }
+
+        if !full_path.as_str().ends_with('/') {
+            let location = format!("{}/", full_path.as_str());
+            return Ok(warp::http::Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header("location", location)
+                .body(warp::hyper::Body::empty())
+                .unwrap());
+        }
+
+        let share_name = share_root
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let display_path = format!("{}/{}", share_name, decoded_tail);
+        return serve_directory(&file_path, &display_path, &headers, |path| {
+            acl::visible(&owner, path)
+        })
+        .await;
+    }
+
+    if !acl::allowed(&owner, &file_path, Permission::Read) {
+        return Ok(text_response("Access denied", StatusCode::FORBIDDEN));
+    }
+
+    // Every request that gets content counts, except further requests from
+    // the same client for the same file version shortly after, so seeking
+    // inside a shared video or resuming a download is one retrieval
+    let retrieval = format!(
+        "{}\0{}\0{}\0{}\0{:?}",
+        share.token,
+        remote.map(|addr| addr.ip().to_string()).unwrap_or_default(),
+        file_path.display(),
+        metadata.len(),
+        metadata.modified().ok()
+    );
+    let counted = head || continues_retrieval(&retrieval);
+    if !counted && !downloads_left(&share.token) {
+        return Ok(text_response("Download limit reached", StatusCode::GONE));
+    }
+
+    let resize = ResizeQuery::from_query(&query);
+    let response = serve_file(&file_path, &headers, metadata.len(), &resize, head).await?;
+
+    let delivered = matches!(
+        response.status(),
+        StatusCode::OK | StatusCode::PARTIAL_CONTENT
+    );
+    if !counted && delivered {
+        if !register_download(&share.token) {
+            return Ok(text_response("Download limit reached", StatusCode::GONE));
+        }
+        remember_retrieval(retrieval);
+    }
+    Ok(response)
+}
+
+fn downloads_left(token: &str) -> bool {
+    shares().read(|items| {
+        items
+            .iter()
+            .find(|share| share.token == token)
+            .is_some_and(|share| share.max_downloads.is_none_or(|max| share.downloads < max))
+    })
+}
+
+fn retrievals() -> &'static Mutex<HashMap<String, u64>> {
+    RETRIEVALS.get_or_init(Default::default)
+}
+
+/// Whether `key` was counted within the last `RETRIEVAL_WINDOW`, refreshing it.
+fn continues_retrieval(key: &str) -> bool {
+    let now = now_millis();
+    let mut retrievals = retrievals().lock().unwrap();
+    match retrievals.get_mut(key) {
+        Some(last) if now.saturating_sub(*last) < RETRIEVAL_WINDOW => {
+            *last = now;
+            true
+        }
+        _ => false,
+    }
+}
+
+fn remember_retrieval(key: String) {
+    let now = now_millis();
+    let mut retrievals = retrievals().lock().unwrap();
+    if retrievals.len() >= RETRIEVAL_CAPACITY {
+        retrievals.retain(|_, last| now.saturating_sub(*last) < RETRIEVAL_WINDOW);
+    }
+    if retrievals.len() >= RETRIEVAL_CAPACITY {
+        if let Some(evicted) = retrievals.keys().next().cloned() {
+            retrievals.remove(&evicted);
+        }
+    }
+    retrievals.insert(key, now);
+}
+
+fn register_download(token: &str) -> bool {
+    shares().update(|items| {
+        let Some(share) = items.iter_mut().find(|share| share.token == token) else {
+            return false;
+        };
+
+        if share
+            .max_downloads
+            .is_some_and(|max| share.downloads >= max)
+        {
+            return false;
+        }
+
+        share.downloads += 1;
+        true
+    })
+}
+
+fn share_info(share: &Share, is_dir: bool) -> ShareInfo {
+    ShareInfo {
+        token: share.token.clone(),
+        url: if is_dir {
+            format!("/s/{}/", share.token)
+        } else {
+            format!("/s/{}", share.token)
+        },
+        path: share.path.clone(),
+        owner: share.owner.clone(),
+        created: share.created,
+        expires: share.expires,
+        has_password: share.password.is_some(),
+        max_downloads: share.max_downloads,
+        downloads: share.downloads,
+    }
+}
+
+fn json_response(message: &str, status: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&message), status).into_response()
+}
+
+fn text_response(message: &'static str, status: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(message, status).into_response()
+}
@@ -0,0 +1,104 @@
+use crate::acl::{self, Permission};
+use crate::auth::Identity;
+use crate::thumbnail::{self, Preview, Thumbnail};
+use crate::types::{data_path, ThumbQuery};
+use percent_encoding::percent_decode_str;
+use std::convert::Infallible;
+use std::sync::OnceLock;
+use tokio::fs;
+use tokio::sync::Semaphore;
+use warp::http::{HeaderMap, StatusCode};
+use warp::hyper::Body;
+use warp::Reply;
+
+static RENDER_SEMAPHORE: OnceLock<Semaphore> = OnceLock::new();
+
+// Decoding large photos and videos is CPU and memory heavy, so a gallery full
+// of them must not start one decode per tile at once
+pub(crate) fn get_render_semaphore() -> &'static Semaphore {
+    RENDER_SEMAPHORE.get_or_init(|| {
+        let workers = std::thread::available_parallelism().map_or(2, |count| count.get());
+        Semaphore::new(workers)
+    })
+}
+
+pub async fn handle_thumb(
+    query: ThumbQuery,
+    headers: HeaderMap,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    let decoded_path = percent_decode_str(&query.path).decode_utf8_lossy();
+    let Some(file_path) = data_path(decoded_path.as_ref()) else {
+        return Ok(text_response("Access denied", StatusCode::FORBIDDEN));
+    };
+
+    if !acl::allowed(&identity, &file_path, Permission::Read) {
+        return Ok(text_response("Access denied", StatusCode::FORBIDDEN));
+    }
+
+    let metadata = match fs::metadata(&file_path).await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return Ok(text_response("File not found", StatusCode::NOT_FOUND)),
+    };
+
+    let Some(preview) = Preview::for_path(&file_path, query.sprite) else {
+        return Ok(text_response(
+            "No thumbnail for this file type",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ));
+    };
+
+    let size = thumbnail::size_bucket(query.size);
+    let key = thumbnail::cache_key(&file_path, &metadata, &format!("{:?}:{}", preview, size));
+    let etag = format!("\"{}\"", key);
+
+    let not_modified = headers
+        .get("if-none-match")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .any(|tag| tag.trim() == etag || tag.trim() == "*")
+        });
+    if not_modified {
+        return Ok(cached_response(StatusCode::NOT_MODIFIED, &etag)
+            .body(Body::empty())
+            .unwrap());
+    }
+
+    let _permit = get_render_semaphore().acquire().await.unwrap();
+    let rendered =
+        tokio::task::spawn_blocking(move || thumbnail::thumbnail(&file_path, &key, preview, size))
+            .await;
+    let Ok(Ok(Thumbnail { path, content_type })) = rendered else {
+        return Ok(text_response(
+            "Cannot decode file",
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ));
+    };
+
+    match fs::read(&path).await {
+        Ok(bytes) => Ok(cached_response(StatusCode::OK, &etag)
+            .header("content-type", content_type)
+            .header("content-length", bytes.len())
+            .body(Body::from(bytes))
+            .unwrap()),
+        Err(_) => Ok(text_response(
+            "Cannot read thumbnail",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )),
+    }
+}
+
+/// The same URL serves a new thumbnail once the image changes, so browsers
+/// may keep one for a day but revalidate it with the ETag afterwards.
+fn cached_response(status: StatusCode, etag: &str) -> warp::http::response::Builder {
+    warp::http::Response::builder()
+        .status(status)
+        .header("etag", etag)
+        .header("cache-control", "private, max-age=86400")
+}
+
+fn text_response(message: &'static str, status: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(message, status).into_response()
+}
@@ -0,0 +1,457 @@
+use super::capabilities::read_only_response;
+use super::upload::{get_upload_semaphore, place_upload, resolve_upload_path, ExclusiveUpload};
+use crate::acl::{self, Permission};
+use crate::auth::{self, Identity};
+use crate::types::{api_path, data_path, now_millis, read_only, state_dir};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use bytes::Buf;
+use futures_util::{Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use warp::http::{HeaderMap, Method, StatusCode};
+use warp::hyper::Body;
+
+pub const TUS_PREFIX: &str = "/api/tus";
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,termination";
+const TUS_MAX_SIZE: u64 = 1024 * 1024 * 1024 * 256; // 256GB, same as multipart uploads
+const DEFAULT_MAX_AGE_HOURS: u64 = 24;
+const GC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize, Deserialize)]
+struct TusUpload {
+    id: String,
+    owner: String,
+    /// Target folder as an API path
+    path: String,
+    filename: String,
+    length: u64,
+    created: u64,
+    updated: u64,
+}
+
+pub async fn handle_tus<S, B>(
+    method: Method,
+    tail: warp::path::Tail,
+    headers: HeaderMap,
+    body: S,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: Buf,
+{
+    if method == Method::OPTIONS {
+        return Ok(options_response());
+    }
+
+    if read_only() && method != Method::HEAD {
+        return Ok(read_only_response());
+    }
+
+    if header_str(&headers, "tus-resumable") != Some(TUS_VERSION) {
+        return Ok(tus_response(StatusCode::PRECONDITION_FAILED)
+            .header("tus-version", TUS_VERSION)
+            .body(Body::empty())
+            .unwrap());
+    }
+
+    let id = tail.as_str().trim_matches('/');
+    let response = match (method.as_str(), id.is_empty()) {
+        ("POST", true) => create(&headers, &identity).await,
+        ("HEAD", false) => head(id, &identity).await,
+        ("PATCH", false) => patch(id, &headers, body, &identity).await,
+        ("DELETE", false) => terminate(id, &identity).await,
+        _ => tus_response(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::empty())
+            .unwrap(),
+    };
+
+    Ok(response)
+}
+
+fn options_response() -> warp::reply::Response {
+    tus_response(StatusCode::NO_CONTENT)
+        .header("tus-version", TUS_VERSION)
+        .header("tus-extension", TUS_EXTENSIONS)
+        .header("tus-max-size", TUS_MAX_SIZE.to_string())
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn create(headers: &HeaderMap, identity: &Identity) -> warp::reply::Response {
+    let Some(length) = header_str(headers, "upload-length").and_then(|v| v.parse::<u64>().ok())
+    else {
+        return error_response(StatusCode::BAD_REQUEST, "Upload-Length is required");
+    };
+    if length > TUS_MAX_SIZE {
+        return error_response(StatusCode::PAYLOAD_TOO_LARGE, "Upload too large");
+    }
+
+    let metadata = parse_metadata(header_str(headers, "upload-metadata").unwrap_or_default());
+    let Some(filename) = metadata_value(&metadata, "filename")
+        .or_else(|| metadata_value(&metadata, "name"))
+        .and_then(|name| {
+            Path::new(&name)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(str::to_owned)
+        })
+    else {
+        return error_response(StatusCode::BAD_REQUEST, "filename metadata is required");
+    };
+
+    let target = metadata_value(&metadata, "path").unwrap_or_default();
+    let Some(target_dir) = data_path(&target) else {
+        return error_response(StatusCode::FORBIDDEN, "Access denied");
+    };
+    if !acl::allowed(identity, &target_dir, Permission::Write) {
+        return error_response(StatusCode::FORBIDDEN, "Access denied");
+    }
+    // Same checks as multipart uploads, so hidden server files cannot be created
+    let Some((_, filename)) = resolve_upload_path(&target_dir, &filename) else {
+        return error_response(StatusCode::BAD_REQUEST, "Invalid file name");
+    };
+
+    let now = now_millis();
+    let upload = TusUpload {
+        id: auth::random_token(),
+        owner: identity.name.clone(),
+        path: api_path(&target_dir),
+        filename,
+        length,
+        created: now,
+        updated: now,
+    };
+
+    if fs::create_dir_all(staging_dir()).await.is_err()
+        || fs::File::create(data_file(&upload.id)).await.is_err()
+        || save_info(&upload).await.is_err()
+    {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create upload");
+    }
+
+    if length == 0 {
+        if let Err(response) = finish(&upload).await {
+            return response;
+        }
+    }
+
+    tus_response(StatusCode::CREATED)
+        .header("location", format!("{}/{}", TUS_PREFIX, upload.id))
+        .header("upload-offset", "0")
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn head(id: &str, identity: &Identity) -> warp::reply::Response {
+    let Some(upload) = load_info(id, identity).await else {
+        return error_response(StatusCode::NOT_FOUND, "Upload not found");
+    };
+
+    let offset = current_offset(id).await;
+    tus_response(StatusCode::OK)
+        .header("upload-offset", offset.to_string())
+        .header("upload-length", upload.length.to_string())
+        .header("cache-control", "no-store")
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn patch<S, B>(
+    id: &str,
+    headers: &HeaderMap,
+    body: S,
+    identity: &Identity,
+) -> warp::reply::Response
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: Buf,
+{
+    if header_str(headers, "content-type") != Some("application/offset+octet-stream") {
+        return error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type must be application/offset+octet-stream",
+        );
+    }
+
+    let Some(mut upload) = load_info(id, identity).await else {
+        return error_response(StatusCode::NOT_FOUND, "Upload not found");
+    };
+
+    let Some(_exclusive) = ExclusiveUpload::acquire(&format!("tus:{}", id)) else {
+        return error_response(StatusCode::CONFLICT, "Upload is already in progress");
+    };
+    let _permit = get_upload_semaphore().acquire().await.unwrap();
+
+    let offset = current_offset(id).await;
+    let requested_offset =
+        header_str(headers, "upload-offset").and_then(|value| value.parse::<u64>().ok());
+    if requested_offset != Some(offset) {
+        return tus_response(StatusCode::CONFLICT)
+            .header("upload-offset", offset.to_string())
+            .body(Body::from("Upload-Offset does not match"))
+            .unwrap();
+    }
+
+    let mut file = match fs::OpenOptions::new()
+        .append(true)
+        .open(data_file(id))
+        .await
+    {
+        Ok(file) => file,
+        Err(_) => return error_response(StatusCode::NOT_FOUND, "Upload not found"),
+    };
+
+    // Whatever arrives before a disconnect is kept, so the client can resume
+    let mut written = offset;
+    let mut body = Box::pin(body);
+    let mut failure = None;
+    loop {
+        match body.try_next().await {
+            Ok(Some(mut chunk)) => {
+                while chunk.has_remaining() {
+                    let bytes = chunk.chunk();
+                    let len = bytes.len();
+                    if written + len as u64 > upload.length {
+                        failure = Some(error_response(
+                            StatusCode::PAYLOAD_TOO_LARGE,
+                            "Upload exceeds Upload-Length",
+                        ));
+                        break;
+                    }
+                    if file.write_all(bytes).await.is_err() {
+                        failure = Some(error_response(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            "Failed to write upload",
+                        ));
+                        break;
+                    }
+                    written += len as u64;
+                    chunk.advance(len);
+                }
+                if failure.is_some() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(_) => {
+                failure = Some(error_response(
+                    StatusCode::BAD_REQUEST,
+                    "Failed to read upload stream",
+                ));
+                break;
+            }
+        }
+    }
+
+    let _ = file.flush().await;
+    drop(file);
+
+    upload.updated = now_millis();
+    let _ = save_info(&upload).await;
+
+    if let Some(response) = failure {
+        return response;
+    }
+
+    if written == upload.length {
+        if let Err(response) = finish(&upload).await {
+            return response;
+        }
+    }
+
+    tus_response(StatusCode::NO_CONTENT)
+        .header("upload-offset", written.to_string())
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn terminate(id: &str, identity: &Identity) -> warp::reply::Response {
+    if load_info(id, identity).await.is_none() {
+        return error_response(StatusCode::NOT_FOUND, "Upload not found");
+    }
+
+    let Some(_exclusive) = ExclusiveUpload::acquire(&format!("tus:{}", id)) else {
+        return error_response(StatusCode::CONFLICT, "Upload is already in progress");
+    };
+
+    remove_upload(id).await;
+    tus_response(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Moves a completed upload into its target folder and drops its staging state.
+async fn finish(upload: &TusUpload) -> Result<PathBuf, warp::reply::Response> {
+    let target_dir = data_path(&upload.path)
+        .ok_or_else(|| error_response(StatusCode::FORBIDDEN, "Access denied"))?;
+
+    if fs::create_dir_all(&target_dir).await.is_err() {
+        return Err(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to create upload directory",
+        ));
+    }
+
+    let final_path = place_upload(&data_file(&upload.id), &target_dir, &upload.filename)
+        .await
+        .map_err(|_| error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save file"))?;
+
+    let _ = fs::remove_file(info_file(&upload.id)).await;
+    Ok(final_path)
+}
+
+/// Periodically removes partial uploads, and data files left without their
+/// info file, that have not been touched for `TUS_MAX_AGE_HOURS` (default 24).
+pub async fn collect_stale_uploads() {
+    let max_age = std::env::var("TUS_MAX_AGE_HOURS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_MAX_AGE_HOURS)
+        * 60
+        * 60
+        * 1000;
+
+    loop {
+        if let Ok(mut entries) = fs::read_dir(staging_dir()).await {
+            let now = now_millis();
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let path = entry.path();
+                match path.extension().and_then(|ext| ext.to_str()) {
+                    Some("json") => {}
+                    // Data whose info file is gone, e.g. after a crash
+                    Some("bin") => {
+                        let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                            continue;
+                        };
+                        let stale = entry
+                            .metadata()
+                            .await
+                            .ok()
+                            .and_then(|metadata| metadata.modified().ok())
+                            .and_then(|modified| modified.elapsed().ok())
+                            .is_some_and(|age| age.as_millis() as u64 > max_age);
+                        if stale && fs::metadata(info_file(id)).await.is_err() {
+                            if let Some(_exclusive) =
+                                ExclusiveUpload::acquire(&format!("tus:{}", id))
+                            {
+                                remove_upload(id).await;
+                            }
+                        }
+                        continue;
+                    }
+                    _ => continue,
+                }
+
+                let Ok(contents) = fs::read(&path).await else {
+                    continue;
+                };
+                let Ok(upload) = serde_json::from_slice::<TusUpload>(&contents) else {
+                    continue;
+                };
+
+                if now.saturating_sub(upload.updated) > max_age {
+                    if let Some(_exclusive) =
+                        ExclusiveUpload::acquire(&format!("tus:{}", upload.id))
+                    {
+                        remove_upload(&upload.id).await;
+                    }
+                }
+            }
+        }
+
+        tokio::time::sleep(GC_INTERVAL).await;
+    }
+}
+
+async fn remove_upload(id: &str) {
+    let _ = fs::remove_file(data_file(id)).await;
+    let _ = fs::remove_file(info_file(id)).await;
+}
+
+async fn load_info(id: &str, identity: &Identity) -> Option<TusUpload> {
+    if !is_valid_id(id) {
+        return None;
+    }
+
+    let contents = fs::read(info_file(id)).await.ok()?;
+    let upload: TusUpload = serde_json::from_slice(&contents).ok()?;
+    (upload.owner == identity.name).then_some(upload)
+}
+
+async fn save_info(upload: &TusUpload) -> std::io::Result<()> {
+    let contents = serde_json::to_vec(upload).map_err(std::io::Error::other)?;
+    let temp_path = staging_dir().join(format!("{}.json.tmp", upload.id));
+    fs::write(&temp_path, contents).await?;
+    fs::rename(&temp_path, info_file(&upload.id)).await
+}
+
+async fn current_offset(id: &str) -> u64 {
+    fs::metadata(data_file(id))
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or(0)
+}
+
+fn staging_dir() -> PathBuf {
+    state_dir().join("tus")
+}
+
+fn data_file(id: &str) -> PathBuf {
+    staging_dir().join(format!("{}.bin", id))
+}
+
+fn info_file(id: &str) -> PathBuf {
+    staging_dir().join(format!("{}.json", id))
+}
+
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+/// Parses `Upload-Metadata`: comma-separated `key base64value` pairs.
+fn parse_metadata(header: &str) -> Vec<(String, String)> {
+    header
+        .split(',')
+        .filter_map(|pair| {
+            let mut parts = pair.trim().splitn(2, ' ');
+            let key = parts.next()?.trim();
+            if key.is_empty() {
+                return None;
+            }
+            let value = match parts.next() {
+                Some(encoded) => String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?,
+                None => String::new(),
+            };
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+fn metadata_value(metadata: &[(String, String)], key: &str) -> Option<String> {
+    metadata
+        .iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.clone())
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn tus_response(status: StatusCode) -> warp::http::response::Builder {
+    warp::http::Response::builder()
+        .status(status)
+        .header("tus-resumable", TUS_VERSION)
+}
+
+fn error_response(status: StatusCode, message: &'static str) -> warp::reply::Response {
+    tus_response(status).body(Body::from(message)).unwrap()
+}
@@ -1,20 +1,36 @@
+use super::conditional::{Precondition, Validators};
+use super::encoding::{compressible, negotiate, Encoding};
 use include_dir::{include_dir, Dir};
 use mime_guess::from_path;
 use std::convert::Infallible;
-use warp::http::StatusCode;
+use warp::http::{HeaderMap, StatusCode};
 use warp::hyper::Body;
 use warp::Filter;
 use warp::Reply;
 
 static UI_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/static");
+/// Written by `build.rs`: `app/index.html` pointing at versioned asset paths,
+/// and the text assets as `<path>.gz`, `<path>.br` and `<path>.zst`
+static UI_BUILT: Dir<'_> = include_dir!("$OUT_DIR/ui");
+// Also written by `build.rs`: `UI_HASHES`, the content hash of every asset by
+// path, and `HASH_LENGTH`, the hex digits of the hash in versioned paths
+include!(concat!(env!("OUT_DIR"), "/ui_hashes.rs"));
 
-fn index_response() -> warp::reply::Response {
-    match UI_DIR.get_file("app/index.html") {
-        Some(file) => warp::http::Response::builder()
-            .status(StatusCode::OK)
-            .header("content-type", "text/html; charset=utf-8")
-            .body(Body::from(file.contents()))
-            .unwrap(),
+const INDEX: &str = "app/index.html";
+/// Versioned paths change whenever the content does, so they are kept for good
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// `index.html` and plain asset paths are revalidated against their `ETag`
+const REVALIDATE: &str = "no-cache";
+
+fn index_response(headers: &HeaderMap) -> warp::reply::Response {
+    match UI_BUILT.get_file(INDEX) {
+        Some(file) => asset_response(
+            INDEX,
+            "text/html; charset=utf-8",
+            file.contents(),
+            REVALIDATE,
+            headers,
+        ),
         None => {
             warp::reply::with_status("Missing app/index.html", StatusCode::INTERNAL_SERVER_ERROR)
                 .into_response()
@@ -22,34 +38,141 @@ fn index_response() -> warp::reply::Response {
     }
 }
 
-fn file_response(path: &str) -> warp::reply::Response {
-    match UI_DIR.get_file(path) {
-        Some(file) => {
-            let content_type = from_path(path).first_or_octet_stream().to_string();
-            warp::http::Response::builder()
-                .status(StatusCode::OK)
-                .header("content-type", content_type)
-                .body(Body::from(file.contents()))
-                .unwrap()
+fn file_response(path: &str, headers: &HeaderMap) -> warp::reply::Response {
+    if path == INDEX {
+        return index_response(headers);
+    }
+    if let Some(file) = UI_DIR.get_file(path) {
+        let content_type = from_path(path).first_or_octet_stream().to_string();
+        return asset_response(path, &content_type, file.contents(), REVALIDATE, headers);
+    }
+
+    // Outdated versions get the current content, but must not be cached for good
+    if let Some((path, hash)) = unversioned(path) {
+        if let Some(file) = UI_DIR.get_file(&path) {
+            let content_type = from_path(&path).first_or_octet_stream().to_string();
+            let cache_control = if content_hash(&path) == Some(hash) {
+                IMMUTABLE
+            } else {
+                REVALIDATE
+            };
+            return asset_response(
+                &path,
+                &content_type,
+                file.contents(),
+                cache_control,
+                headers,
+            );
         }
-        None => index_response(),
+    }
+
+    index_response(headers)
+}
+
+fn content_hash(path: &str) -> Option<&'static str> {
+    UI_HASHES
+        .iter()
+        .find(|(asset, _)| *asset == path)
+        .map(|(_, hash)| *hash)
+}
+
+/// Splits `app/main.<hash>.js`, as `build.rs` names versioned assets, into
+/// `app/main.js` and the hash.
+fn unversioned(path: &str) -> Option<(String, &str)> {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, path),
+    };
+    let mut parts = name.rsplitn(3, '.');
+    let (extension, hash, stem) = (parts.next()?, parts.next()?, parts.next()?);
+    if hash.len() != HASH_LENGTH || !hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let name = format!("{}.{}", stem, extension);
+    let path = match dir {
+        Some(dir) => format!("{}/{}", dir, name),
+        None => name,
+    };
+    Some((path, hash))
+}
+
+/// Sends an asset with its content-hash `ETag`, answering `If-None-Match`
+/// with `304`, and its precompressed copy when the client accepts one.
+fn asset_response(
+    path: &str,
+    content_type: &str,
+    contents: &'static [u8],
+    cache_control: &'static str,
+    headers: &HeaderMap,
+) -> warp::reply::Response {
+    let compressible = compressible(content_type);
+    let encoded =
+        |encoding: Encoding| UI_BUILT.get_file(format!("{}.{}", path, encoding.extension()));
+    let offered: Vec<Encoding> = Encoding::ALL
+        .into_iter()
+        .filter(|encoding| compressible && encoded(*encoding).is_some())
+        .collect();
+    let encoding = negotiate(headers, &offered);
+
+    let validators = content_hash(path).map(|hash| {
+        let validators = Validators::for_hash(hash);
+        match encoding {
+            Some(encoding) => validators.encoded(encoding.token()),
+            None => validators,
+        }
+    });
+    let not_modified = validators.as_ref().is_some_and(|validators| {
+        matches!(validators.evaluate(headers), Precondition::NotModified)
+    });
+
+    let mut builder = warp::http::Response::builder()
+        .header("cache-control", cache_control)
+        .header("content-type", content_type);
+    if compressible {
+        builder = builder.header("vary", "accept-encoding");
+    }
+    if let Some(validators) = &validators {
+        builder = validators.apply(builder);
+    }
+    if not_modified {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    let builder = builder.status(StatusCode::OK);
+    match encoding.and_then(|encoding| Some((encoding, encoded(encoding)?))) {
+        Some((encoding, file)) => builder
+            .header("content-encoding", encoding.token())
+            .body(Body::from(file.contents()))
+            .unwrap(),
+        None => builder.body(Body::from(contents)).unwrap(),
     }
 }
 
-async fn serve_ui_path(tail: warp::path::Tail) -> Result<warp::reply::Response, Infallible> {
+async fn serve_ui_path(
+    tail: warp::path::Tail,
+    headers: HeaderMap,
+) -> Result<warp::reply::Response, Infallible> {
     let path = tail.as_str().trim_start_matches('/');
     if path.is_empty() {
-        return Ok(index_response());
+        return Ok(index_response(&headers));
     }
 
-    Ok(file_response(path))
+    Ok(file_response(path, &headers))
 }
 
 pub fn ui_routes() -> warp::filters::BoxedFilter<(warp::reply::Response,)> {
-    let ui_index = warp::path("ui").and(warp::path::end()).map(index_response);
+    let ui_index = warp::path("ui")
+        .and(warp::path::end())
+        .and(warp::header::headers_cloned())
+        .map(|headers: HeaderMap| index_response(&headers));
 
     let ui_path = warp::path("ui")
         .and(warp::path::tail())
+        .and(warp::header::headers_cloned())
         .and_then(serve_ui_path);
 
     ui_index.or(ui_path).unify().boxed()
@@ -1,11 +1,14 @@
-use crate::types::{data_path, ListQuery};
+use crate::acl::{self, Permission};
+use crate::auth::Identity;
+use crate::types::{api_path, data_path, ConflictPolicy, UploadQuery, PARTIAL_SUFFIX};
 use bytes::Buf;
 use futures_util::TryStreamExt;
 use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use std::collections::HashSet;
 use std::convert::Infallible;
-use std::path::Path;
-use std::sync::OnceLock;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::Semaphore;
@@ -16,13 +19,43 @@ use warp::Reply;
 static UPLOAD_SEMAPHORE: OnceLock<Semaphore> = OnceLock::new();
 
 // Use 3 for better HDD support
-fn get_upload_semaphore() -> &'static Semaphore {
+pub(crate) fn get_upload_semaphore() -> &'static Semaphore {
     UPLOAD_SEMAPHORE.get_or_init(|| Semaphore::new(3))
 }
 
+// Keys of uploads that must not run concurrently with themselves
+static EXCLUSIVE_UPLOADS: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+/// Marks `key` as busy until dropped, including when the client disconnects.
+pub(crate) struct ExclusiveUpload {
+    key: String,
+}
+
+impl ExclusiveUpload {
+    pub(crate) fn acquire(key: &str) -> Option<Self> {
+        let active = EXCLUSIVE_UPLOADS.get_or_init(|| Mutex::new(HashSet::new()));
+        active
+            .lock()
+            .unwrap()
+            .insert(key.to_string())
+            .then(|| Self {
+                key: key.to_string(),
+            })
+    }
+}
+
+impl Drop for ExclusiveUpload {
+    fn drop(&mut self) {
+        if let Some(active) = EXCLUSIVE_UPLOADS.get() {
+            active.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
 pub async fn handle_upload(
-    query: ListQuery,
+    query: UploadQuery,
     mut form: warp::multipart::FormData,
+    identity: Identity,
 ) -> Result<warp::reply::Response, Infallible> {
     // Acquire semaphore permit to limit concurrent uploads globally
     let _permit = get_upload_semaphore().acquire().await.unwrap();
@@ -33,6 +66,10 @@ pub async fn handle_upload(
         return Ok(upload_response("Access denied", StatusCode::FORBIDDEN));
     };
 
+    if !acl::allowed(&identity, &target_dir, Permission::Write) {
+        return Ok(upload_response("Access denied", StatusCode::FORBIDDEN));
+    }
+
     if let Err(e) = fs::create_dir_all(&target_dir).await {
         return Ok(upload_response(
             format!("Failed to create upload directory: {}", e),
@@ -40,55 +77,218 @@ pub async fn handle_upload(
         ));
     }
 
-    let mut uploaded_files = 0;
+    let options = UploadOptions {
+        on_conflict: query.on_conflict,
+        ..UploadOptions::default()
+    };
+    let mut summary = UploadSummary::default();
+    let result = receive_upload(&mut form, &target_dir, &identity, &options, &mut summary).await;
+
+    Ok(summary_response(result, summary))
+}
+
+/// Settings for a single upload request; `None` limits mean unlimited.
+#[derive(Default)]
+pub(crate) struct UploadOptions {
+    pub max_files: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub on_conflict: ConflictPolicy,
+}
+
+#[derive(Default)]
+pub(crate) struct UploadSummary {
+    /// Files and bytes actually stored, excluding skipped files
+    pub files: u64,
+    pub bytes: u64,
+    pub results: Vec<UploadResult>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum UploadStatus {
+    Created,
+    Renamed,
+    Overwritten,
+    Skipped,
+    Conflict,
+}
+
+/// Outcome for one uploaded file.
+#[derive(Serialize)]
+pub(crate) struct UploadResult {
+    /// File name as sent by the client
+    pub name: String,
+    /// Where the file was stored, absent when it was skipped or conflicted
+    pub path: Option<String>,
+    pub bytes: u64,
+    pub status: UploadStatus,
+}
+
+#[derive(Serialize)]
+struct UploadResponse<'a> {
+    message: String,
+    files: &'a [UploadResult],
+}
+
+/// Stores every `file` part of `form` in `target_dir`, accumulating into
+/// `summary` so callers can account for files saved before an error.
+pub(crate) async fn receive_upload(
+    form: &mut warp::multipart::FormData,
+    target_dir: &Path,
+    identity: &Identity,
+    options: &UploadOptions,
+    summary: &mut UploadSummary,
+) -> Result<(), (StatusCode, String)> {
+    // Set by a `relative_path` field and used by the next `file` part
+    let mut relative_path = None;
 
     loop {
         match form.try_next().await {
             Ok(Some(part)) => {
+                if part.name() == "relative_path" {
+                    relative_path = Some(read_text_part(part).await?);
+                    continue;
+                }
                 if part.name() != "file" {
                     continue;
                 }
 
-                let Some(filename) = part.filename().map(str::to_owned) else {
+                let Some(requested) = relative_path
+                    .take()
+                    .or_else(|| part.filename().map(str::to_owned))
+                    .filter(|name| !name.trim_matches('/').is_empty())
+                else {
                     continue;
                 };
+                let requested = requested.trim_matches('/').to_string();
+
+                let Some((file_dir, filename)) = resolve_upload_path(target_dir, &requested) else {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        format!("Invalid file name: {}", requested),
+                    ));
+                };
 
-                if let Err((status, message)) = save_upload_part(part, &target_dir, &filename).await
-                {
-                    return Ok(upload_response(message, status));
+                if !acl::allowed(identity, &file_dir, Permission::Write) {
+                    return Err((
+                        StatusCode::FORBIDDEN,
+                        format!("Access denied: {}", requested),
+                    ));
                 }
 
-                uploaded_files += 1;
+                if options.max_files.is_some_and(|max| summary.files >= max) {
+                    return Err((
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        "File count limit reached".to_string(),
+                    ));
+                }
+
+                if let Err(e) = fs::create_dir_all(&file_dir).await {
+                    return Err((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to create upload directory: {}", e),
+                    ));
+                }
+
+                let remaining_bytes = options
+                    .max_bytes
+                    .map(|max| max.saturating_sub(summary.bytes));
+                let mut result = save_upload_part(
+                    part,
+                    &file_dir,
+                    &filename,
+                    remaining_bytes,
+                    options.on_conflict,
+                )
+                .await?;
+                result.name = requested;
+
+                if result.path.is_some() {
+                    summary.files += 1;
+                    summary.bytes += result.bytes;
+                }
+                summary.results.push(result);
             }
-            Ok(None) => break,
+            Ok(None) => return Ok(()),
             Err(e) => {
-                return Ok(upload_response(
-                    format!("Failed to process upload: {}", e),
+                return Err((
                     StatusCode::BAD_REQUEST,
+                    format!("Failed to process upload: {}", e),
                 ));
             }
         }
     }
+}
+
+/// Resolves a client supplied name such as `photos/2024/a.jpg` below
+/// `target_dir`, returning the folder to store it in and the bare file name.
+pub(crate) fn resolve_upload_path(target_dir: &Path, requested: &str) -> Option<(PathBuf, String)> {
+    let path = data_path(&format!("{}/{}", api_path(target_dir), requested))?;
+    if !path.starts_with(target_dir) || path == target_dir {
+        return None;
+    }
+
+    let filename = path.file_name()?.to_str()?.to_owned();
+    Some((path.parent()?.to_path_buf(), filename))
+}
+
+async fn read_text_part(part: warp::multipart::Part) -> Result<String, (StatusCode, String)> {
+    const MAX_LENGTH: usize = 4096;
+
+    let mut stream = part.stream();
+    let mut contents = Vec::new();
+    while let Some(chunk) = stream.try_next().await.map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Failed to process upload: {}", e),
+        )
+    })? {
+        contents.extend_from_slice(chunk.chunk());
+        if contents.len() > MAX_LENGTH {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "relative_path is too long".to_string(),
+            ));
+        }
+    }
 
-    Ok(upload_response(
-        format!("Successfully uploaded {} file(s)", uploaded_files),
-        StatusCode::OK,
-    ))
+    String::from_utf8(contents)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid relative_path".to_string()))
 }
 
 async fn save_upload_part(
     part: warp::multipart::Part,
     target_dir: &Path,
     filename: &str,
-) -> Result<(), (StatusCode, String)> {
-    let mut stream = part.stream();
-    let mut file = open_upload_file(target_dir, filename).await.map_err(|e| {
+    max_bytes: Option<u64>,
+    on_conflict: ConflictPolicy,
+) -> Result<UploadResult, (StatusCode, String)> {
+    let save_error = |e: std::io::Error| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Failed to save file: {}", e),
         )
-    })?;
+    };
+
+    // Avoid receiving a file that will be thrown away; placing it re-checks
+    if matches!(on_conflict, ConflictPolicy::Skip | ConflictPolicy::Fail)
+        && fs::symlink_metadata(target_dir.join(filename))
+            .await
+            .is_ok()
+    {
+        return Ok(UploadResult {
+            name: filename.to_string(),
+            path: None,
+            bytes: 0,
+            status: conflict_status(on_conflict),
+        });
+    }
+
+    let mut stream = part.stream();
+    let (mut file, partial) = PartialFile::create(target_dir).await.map_err(save_error)?;
+    let mut written = 0u64;
 
+    // Returning early drops `partial`, which removes the incomplete file
     loop {
         match stream.try_next().await {
             Ok(Some(mut chunk)) => {
@@ -98,17 +298,20 @@ async fn save_upload_part(
                         break;
                     }
 
-                    file.write_all(bytes).await.map_err(|e| {
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            format!("Failed to save file: {}", e),
-                        )
-                    })?;
+                    written += bytes.len() as u64;
+                    if max_bytes.is_some_and(|max| written > max) {
+                        return Err((
+                            StatusCode::PAYLOAD_TOO_LARGE,
+                            "Upload size limit reached".to_string(),
+                        ));
+                    }
+
+                    file.write_all(bytes).await.map_err(save_error)?;
 
                     chunk.advance(bytes.len());
                 }
             }
-            Ok(None) => return Ok(()),
+            Ok(None) => break,
             Err(e) => {
                 return Err((
                     StatusCode::BAD_REQUEST,
@@ -117,6 +320,65 @@ async fn save_upload_part(
             }
         }
     }
+
+    file.flush().await.map_err(save_error)?;
+    drop(file);
+
+    let (status, final_path) = partial
+        .place(target_dir, filename, on_conflict)
+        .await
+        .map_err(save_error)?;
+
+    Ok(UploadResult {
+        name: filename.to_string(),
+        path: final_path.as_deref().map(api_path),
+        bytes: if final_path.is_some() { written } else { 0 },
+        status,
+    })
+}
+
+/// Builds the JSON reply for a finished upload: the per-file results plus a
+/// message, with 409 when any file hit an existing name under `fail`.
+pub(crate) fn summary_response(
+    result: Result<(), (StatusCode, String)>,
+    summary: UploadSummary,
+) -> warp::reply::Response {
+    let (status, message) = match result {
+        Err(error) => error,
+        Ok(()) => {
+            let skipped = summary
+                .results
+                .iter()
+                .filter(|result| result.status == UploadStatus::Skipped)
+                .count();
+            let conflicts = summary
+                .results
+                .iter()
+                .filter(|result| result.status == UploadStatus::Conflict)
+                .count();
+
+            let mut message = format!("Successfully uploaded {} file(s)", summary.files);
+            if skipped > 0 {
+                message.push_str(&format!(", skipped {} existing", skipped));
+            }
+            if conflicts > 0 {
+                message.push_str(&format!(", {} already existed", conflicts));
+            }
+
+            let status = if conflicts > 0 {
+                StatusCode::CONFLICT
+            } else {
+                StatusCode::OK
+            };
+            (status, message)
+        }
+    };
+
+    let response = UploadResponse {
+        message,
+        files: &summary.results,
+    };
+    warp::reply::with_status(warp::reply::json(&response), status).into_response()
 }
 
 fn upload_response(message: impl Into<String>, status: StatusCode) -> warp::reply::Response {
@@ -124,43 +386,169 @@ fn upload_response(message: impl Into<String>, status: StatusCode) -> warp::repl
     warp::reply::with_status(warp::reply::json(&message), status).into_response()
 }
 
-async fn open_upload_file(target_dir: &Path, filename: &str) -> std::io::Result<tokio::fs::File> {
-    use tokio::fs::OpenOptions;
+fn conflict_status(on_conflict: ConflictPolicy) -> UploadStatus {
+    if on_conflict == ConflictPolicy::Skip {
+        UploadStatus::Skipped
+    } else {
+        UploadStatus::Conflict
+    }
+}
 
-    let original_path = target_dir.join(filename);
+/// `name.ext` for 0, otherwise `name (n).ext`; `.tar.*` stays one extension.
+fn numbered_filename(filename: &str, n: u32) -> String {
+    if n == 0 {
+        return filename.to_string();
+    }
 
-    match OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(&original_path)
-        .await
-    {
-        Ok(file) => Ok(file),
-        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
-            let nanos = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_nanos();
-            let suffix = nanos % 1_000_000;
-
-            let timestamped_filename = if let Some(dot_pos) = filename.rfind('.') {
-                format!(
-                    "{}_{}{}",
-                    &filename[..dot_pos],
-                    suffix,
-                    &filename[dot_pos..]
-                )
-            } else {
-                format!("{}_{}", filename, suffix)
-            };
+    let path = Path::new(filename);
+    let stem = path.file_stem().and_then(|stem| stem.to_str());
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    let (stem, extension) = match (stem, extension) {
+        (Some(stem), Some(extension)) => match stem.strip_suffix(".tar") {
+            Some(base) if !base.is_empty() => (base, format!(".tar.{}", extension)),
+            _ => (stem, format!(".{}", extension)),
+        },
+        _ => (filename, String::new()),
+    };
+
+    format!("{} ({}){}", stem, n, extension)
+}
+
+/// A fresh hidden name in `dir` for data that is not in place yet; listings
+/// and the API never show it.
+pub(crate) fn partial_path(dir: &Path) -> std::io::Result<PathBuf> {
+    let mut random = [0u8; 8];
+    getrandom::getrandom(&mut random).map_err(std::io::Error::other)?;
+    let name: String = random.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok(dir.join(format!(".{}{}", name, PARTIAL_SUFFIX)))
+}
 
-            let timestamped_path = target_dir.join(timestamped_filename);
-            OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(timestamped_path)
-                .await
+/// A hidden file inside the destination folder that receives an upload.
+/// It is removed when dropped unless it was moved into place, so failed or
+/// interrupted uploads never leave half-written files behind.
+pub(crate) struct PartialFile {
+    path: PathBuf,
+    kept: bool,
+}
+
+impl PartialFile {
+    pub(crate) async fn create(dir: &Path) -> std::io::Result<(fs::File, Self)> {
+        let path = partial_path(dir)?;
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+        Ok((file, Self { path, kept: false }))
+    }
+
+    /// Moves the file into `target_dir` as `filename` following `on_conflict`.
+    /// Returns no path when the file was discarded because the name was taken.
+    pub(crate) async fn place(
+        mut self,
+        target_dir: &Path,
+        filename: &str,
+        on_conflict: ConflictPolicy,
+    ) -> std::io::Result<(UploadStatus, Option<PathBuf>)> {
+        let final_path = target_dir.join(filename);
+
+        match on_conflict {
+            ConflictPolicy::Overwrite => {
+                let existed = fs::symlink_metadata(&final_path).await.is_ok();
+                self.replace(&final_path).await?;
+                let status = if existed {
+                    UploadStatus::Overwritten
+                } else {
+                    UploadStatus::Created
+                };
+                Ok((status, Some(final_path)))
+            }
+            ConflictPolicy::Rename => {
+                let mut n = 0;
+                loop {
+                    let candidate = target_dir.join(numbered_filename(filename, n));
+                    match self.claim(&candidate).await {
+                        Ok(()) if n == 0 => return Ok((UploadStatus::Created, Some(candidate))),
+                        Ok(()) => return Ok((UploadStatus::Renamed, Some(candidate))),
+                        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            ConflictPolicy::Skip | ConflictPolicy::Fail => match self.claim(&final_path).await {
+                Ok(()) => Ok((UploadStatus::Created, Some(final_path))),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    Ok((conflict_status(on_conflict), None))
+                }
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    /// Atomically replaces `file_path`, keeping its permissions if it exists.
+    pub(crate) async fn replace(mut self, file_path: &Path) -> std::io::Result<()> {
+        if let Ok(metadata) = fs::metadata(file_path).await {
+            fs::set_permissions(&self.path, metadata.permissions()).await?;
+        }
+
+        fs::rename(&self.path, file_path).await?;
+        self.kept = true;
+        Ok(())
+    }
+
+    /// Moves the file to `target` unless something already exists there.
+    async fn claim(&mut self, target: &Path) -> std::io::Result<()> {
+        // A hard link never replaces an existing file, unlike rename
+        match fs::hard_link(&self.path, target).await {
+            Ok(()) => {
+                self.kept = true;
+                let _ = fs::remove_file(&self.path).await;
+                Ok(())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Err(e),
+            Err(_) => {
+                // Some filesystems have no hard links; fall back to check-then-rename
+                if fs::symlink_metadata(target).await.is_ok() {
+                    return Err(std::io::ErrorKind::AlreadyExists.into());
+                }
+                fs::rename(&self.path, target).await?;
+                self.kept = true;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if !self.kept {
+            let _ = std::fs::remove_file(&self.path);
         }
-        Err(e) => Err(e),
     }
 }
+
+/// Moves a fully written file from a staging location into `target_dir`,
+/// keeping any existing file by numbering the new one, and returns where it
+/// ended up. The staged file is only removed once it has been placed.
+pub(crate) async fn place_upload(
+    staged: &Path,
+    target_dir: &Path,
+    filename: &str,
+) -> std::io::Result<PathBuf> {
+    let (file, partial) = PartialFile::create(target_dir).await?;
+    drop(file);
+
+    // The staged file stays until placement succeeds, so a failure can be
+    // retried. Staging may live on another filesystem, where linking fails.
+    fs::remove_file(&partial.path).await?;
+    if fs::hard_link(staged, &partial.path).await.is_err() {
+        fs::copy(staged, &partial.path).await?;
+    }
+
+    let (_, final_path) = partial
+        .place(target_dir, filename, ConflictPolicy::Rename)
+        .await?;
+    let final_path = final_path.ok_or(std::io::ErrorKind::AlreadyExists)?;
+    let _ = fs::remove_file(staged).await;
+    Ok(final_path)
+}
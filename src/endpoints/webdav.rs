@@ -0,0 +1,600 @@
+use super::capabilities::read_only_response;
+use super::delete::{is_data_root, remove_path, resolved};
+use super::file_server::{serve_directory, serve_file};
+use super::mkdir::create_folder;
+use super::mv::move_path;
+use super::upload::{partial_path, PartialFile};
+use crate::acl::{self, Permission};
+use crate::auth::Identity;
+use crate::types::{data_dir, data_path, is_internal, read_only, ResizeQuery};
+use bytes::Buf;
+use futures_util::{Stream, TryStreamExt};
+use mime_guess::from_path;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use walkdir::WalkDir;
+use warp::http::{HeaderMap, Method, StatusCode};
+use warp::hyper::Body;
+
+pub const DAV_PREFIX: &str = "/dav";
+
+const ALLOWED_METHODS: &str =
+    "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL, COPY, MOVE, PROPFIND, PROPPATCH, LOCK, UNLOCK";
+const READ_ONLY_METHODS: &str = "OPTIONS, GET, HEAD, PROPFIND";
+
+const HREF_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'`')
+    .add(b'#')
+    .add(b'?')
+    .add(b'[')
+    .add(b']')
+    .add(b'{')
+    .add(b'}');
+
+pub async fn handle_webdav<S, B>(
+    method: Method,
+    path: warp::path::Tail,
+    headers: HeaderMap,
+    body: S,
+    identity: Identity,
+) -> Result<warp::reply::Response, Infallible>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: Buf,
+{
+    let decoded_path = percent_decode_str(path.as_str())
+        .decode_utf8_lossy()
+        .into_owned();
+    let Some(file_path) = data_path(&decoded_path) else {
+        return Ok(status_response(StatusCode::FORBIDDEN));
+    };
+    let relative = decoded_path.trim_matches('/').to_string();
+
+    let is_read = matches!(method.as_str(), "OPTIONS" | "GET" | "HEAD" | "PROPFIND");
+    if read_only() && !is_read {
+        return Ok(read_only_response());
+    }
+
+    let permitted = match method.as_str() {
+        "OPTIONS" | "GET" | "HEAD" | "PROPFIND" => acl::visible(&identity, &file_path),
+        "DELETE" | "MOVE" => acl::allowed(&identity, &file_path, Permission::Delete),
+        "COPY" => acl::allowed(&identity, &file_path, Permission::Read),
+        _ => acl::allowed(&identity, &file_path, Permission::Write),
+    };
+    if !permitted {
+        return Ok(status_response(StatusCode::FORBIDDEN));
+    }
+
+    let response = match method.as_str() {
+        "OPTIONS" => options_response(),
+        "GET" | "HEAD" => {
+            let head = method.as_str() == "HEAD";
+            return get(&file_path, path.as_str(), head, &headers, &identity).await;
+        }
+        "PROPFIND" => propfind(&file_path, &relative, &headers, &identity).await,
+        "PROPPATCH" => proppatch(&file_path, &relative).await,
+        "MKCOL" => mkcol(&file_path).await,
+        "PUT" => put(&file_path, body).await,
+        "DELETE" => delete(&file_path).await,
+        "COPY" => transfer(&file_path, &headers, false, &identity).await,
+        "MOVE" => transfer(&file_path, &headers, true, &identity).await,
+        "LOCK" => lock(&file_path, &relative).await,
+        "UNLOCK" => status_response(StatusCode::NO_CONTENT),
+        _ => warp::http::Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header("allow", ALLOWED_METHODS)
+            .body(Body::empty())
+            .unwrap(),
+    };
+
+    Ok(response)
+}
+
+fn options_response() -> warp::reply::Response {
+    let allow = if read_only() {
+        READ_ONLY_METHODS
+    } else {
+        ALLOWED_METHODS
+    };
+
+    warp::http::Response::builder()
+        .status(StatusCode::OK)
+        .header("dav", "1, 2")
+        .header("ms-author-via", "DAV")
+        .header("allow", allow)
+        .header("content-length", "0")
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn get(
+    file_path: &Path,
+    requested_path: &str,
+    head: bool,
+    headers: &HeaderMap,
+    identity: &Identity,
+) -> Result<warp::reply::Response, Infallible> {
+    match fs::metadata(file_path).await {
+        Ok(metadata) if metadata.is_dir() => {
+            serve_directory(file_path, requested_path, headers, |path| {
+                acl::visible(identity, path)
+            })
+            .await
+        }
+        Ok(_) if !acl::allowed(identity, file_path, Permission::Read) => {
+            Ok(status_response(StatusCode::FORBIDDEN))
+        }
+        Ok(metadata) => {
+            serve_file(
+                file_path,
+                headers,
+                metadata.len(),
+                &ResizeQuery::default(),
+                head,
+            )
+            .await
+        }
+        Err(_) => Ok(status_response(StatusCode::NOT_FOUND)),
+    }
+}
+
+async fn propfind(
+    file_path: &Path,
+    relative: &str,
+    headers: &HeaderMap,
+    identity: &Identity,
+) -> warp::reply::Response {
+    let metadata = match fs::metadata(file_path).await {
+        Ok(metadata) => metadata,
+        Err(_) => return status_response(StatusCode::NOT_FOUND),
+    };
+
+    // Depth: infinity is answered like Depth: 1 to keep large trees cheap
+    let depth = headers
+        .get("depth")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("infinity");
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    push_prop_response(&mut xml, relative, &metadata);
+
+    if metadata.is_dir() && depth != "0" {
+        if let Ok(mut entries) = fs::read_dir(file_path).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                if is_internal(&entry.path()) || !acl::visible(identity, &entry.path()) {
+                    continue;
+                }
+
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+                let Ok(child_metadata) = entry.metadata().await else {
+                    continue;
+                };
+
+                let child = if relative.is_empty() {
+                    name
+                } else {
+                    format!("{}/{}", relative, name)
+                };
+                push_prop_response(&mut xml, &child, &child_metadata);
+            }
+        }
+    }
+
+    xml.push_str("</D:multistatus>\n");
+    multistatus_response(xml)
+}
+
+async fn proppatch(file_path: &Path, relative: &str) -> warp::reply::Response {
+    let metadata = match fs::metadata(file_path).await {
+        Ok(metadata) => metadata,
+        Err(_) => return status_response(StatusCode::NOT_FOUND),
+    };
+
+    // Dead properties are not stored; acknowledge the request without changes
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    let _ = writeln!(
+        xml,
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop/><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        href_for(relative, metadata.is_dir())
+    );
+    xml.push_str("</D:multistatus>\n");
+    multistatus_response(xml)
+}
+
+async fn mkcol(file_path: &Path) -> warp::reply::Response {
+    if fs::metadata(file_path).await.is_ok() {
+        return status_response(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    if !parent_exists(file_path).await {
+        return status_response(StatusCode::CONFLICT);
+    }
+
+    match create_folder(file_path).await {
+        Ok(_) => status_response(StatusCode::CREATED),
+        Err(_) => status_response(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn put<S, B>(file_path: &Path, body: S) -> warp::reply::Response
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: Buf,
+{
+    if !parent_exists(file_path).await {
+        return status_response(StatusCode::CONFLICT);
+    }
+
+    let existed = match fs::metadata(file_path).await {
+        Ok(metadata) if metadata.is_dir() => {
+            return status_response(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        Ok(_) => true,
+        Err(_) => false,
+    };
+
+    let Some(parent) = file_path.parent() else {
+        return status_response(StatusCode::CONFLICT);
+    };
+    let (mut file, partial) = match PartialFile::create(parent).await {
+        Ok(created) => created,
+        Err(_) => return status_response(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    // Returning early drops `partial`, which removes the incomplete file
+    let mut body = Box::pin(body);
+    loop {
+        match body.try_next().await {
+            Ok(Some(mut chunk)) => {
+                while chunk.has_remaining() {
+                    let bytes = chunk.chunk();
+                    let len = bytes.len();
+                    if file.write_all(bytes).await.is_err() {
+                        return status_response(StatusCode::INTERNAL_SERVER_ERROR);
+                    }
+                    chunk.advance(len);
+                }
+            }
+            Ok(None) => break,
+            Err(_) => return status_response(StatusCode::BAD_REQUEST),
+        }
+    }
+
+    if file.flush().await.is_err() {
+        return status_response(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    drop(file);
+
+    if partial.replace(file_path).await.is_err() {
+        return status_response(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    if existed {
+        status_response(StatusCode::NO_CONTENT)
+    } else {
+        status_response(StatusCode::CREATED)
+    }
+}
+
+async fn delete(file_path: &Path) -> warp::reply::Response {
+    if fs::symlink_metadata(file_path).await.is_err() {
+        return status_response(StatusCode::NOT_FOUND);
+    }
+    if is_data_root(file_path).await {
+        return status_response(StatusCode::FORBIDDEN);
+    }
+
+    match remove_path(file_path).await {
+        Ok(_) => status_response(StatusCode::NO_CONTENT),
+        Err(_) => status_response(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn transfer(
+    file_path: &Path,
+    headers: &HeaderMap,
+    is_move: bool,
+    identity: &Identity,
+) -> warp::reply::Response {
+    let Some(destination) = headers
+        .get("destination")
+        .and_then(|value| value.to_str().ok())
+        .and_then(destination_path)
+    else {
+        return status_response(StatusCode::BAD_REQUEST);
+    };
+    let Some(target_path) = data_path(&destination) else {
+        return status_response(StatusCode::FORBIDDEN);
+    };
+    if !acl::allowed(identity, &target_path, Permission::Write) {
+        return status_response(StatusCode::FORBIDDEN);
+    }
+
+    if fs::metadata(file_path).await.is_err() {
+        return status_response(StatusCode::NOT_FOUND);
+    }
+    // The root holds the state directory, and a copy into itself never ends
+    let source = resolved(file_path).await;
+    let target = resolved(&target_path).await;
+    let root = resolved(data_dir()).await;
+    if source == root || target == root || target.starts_with(&source) {
+        return status_response(StatusCode::FORBIDDEN);
+    }
+    if !parent_exists(&target_path).await {
+        return status_response(StatusCode::CONFLICT);
+    }
+
+    let overwrite = headers
+        .get("overwrite")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| !value.eq_ignore_ascii_case("f"))
+        .unwrap_or(true);
+
+    let existed = fs::symlink_metadata(&target_path).await.is_ok();
+    if existed {
+        if !acl::allowed(identity, &target_path, Permission::Delete) {
+            return status_response(StatusCode::FORBIDDEN);
+        }
+        if !overwrite {
+            return status_response(StatusCode::PRECONDITION_FAILED);
+        }
+    }
+
+    // An existing destination is only replaced once the new one is complete,
+    // so a failed copy or move never loses it
+    let staged = if existed {
+        match target_path.parent().map(partial_path) {
+            Some(Ok(staged)) => staged,
+            _ => return status_response(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    } else {
+        target_path.clone()
+    };
+
+    let result = if is_move {
+        move_path(file_path, &staged).await
+    } else {
+        let from = file_path.to_path_buf();
+        let to = staged.clone();
+        let identity = identity.clone();
+        tokio::task::spawn_blocking(move || {
+            copy_path(&from, &to, |path| acl::visible(&identity, path))
+        })
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+    };
+    let result = match result {
+        Ok(()) if existed => match replace_path(&staged, &target_path).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                if is_move {
+                    let _ = move_path(&staged, file_path).await;
+                } else {
+                    let _ = remove_path(&staged).await;
+                }
+                Err(e)
+            }
+        },
+        Ok(()) => Ok(()),
+        Err(e) => {
+            if !is_move {
+                let _ = remove_path(&staged).await;
+            }
+            Err(e)
+        }
+    };
+
+    match result {
+        Ok(()) if existed => status_response(StatusCode::NO_CONTENT),
+        Ok(()) => status_response(StatusCode::CREATED),
+        Err(_) => status_response(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Puts `staged` in place of the existing `target`, restoring the old one if
+/// that fails.
+async fn replace_path(staged: &Path, target: &Path) -> std::io::Result<()> {
+    let parent = target.parent().ok_or(std::io::ErrorKind::NotFound)?;
+    let previous = partial_path(parent)?;
+    fs::rename(target, &previous).await?;
+    if let Err(e) = fs::rename(staged, target).await {
+        let _ = fs::rename(&previous, target).await;
+        return Err(e);
+    }
+    let _ = remove_path(&previous).await;
+    Ok(())
+}
+
+async fn lock(file_path: &Path, relative: &str) -> warp::reply::Response {
+    // Locks are advisory only: every LOCK succeeds and no token is enforced
+    let mut status = StatusCode::OK;
+    let is_dir = match fs::metadata(file_path).await {
+        Ok(metadata) => metadata.is_dir(),
+        Err(_) => {
+            if !parent_exists(file_path).await || fs::File::create(file_path).await.is_err() {
+                return status_response(StatusCode::CONFLICT);
+            }
+            status = StatusCode::CREATED;
+            false
+        }
+    };
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    let token = format!("opaquelocktoken:mediabrowser-{:x}", nanos);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery><D:activelock>\
+<D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope>\
+<D:depth>infinity</D:depth><D:timeout>Second-3600</D:timeout>\
+<D:locktoken><D:href>{}</D:href></D:locktoken>\
+<D:lockroot><D:href>{}</D:href></D:lockroot>\
+</D:activelock></D:lockdiscovery></D:prop>",
+        token,
+        href_for(relative, is_dir)
+    );
+
+    warp::http::Response::builder()
+        .status(status)
+        .header("content-type", "application/xml; charset=utf-8")
+        .header("lock-token", format!("<{}>", token))
+        .body(Body::from(xml))
+        .unwrap()
+}
+
+fn push_prop_response(xml: &mut String, relative: &str, metadata: &std::fs::Metadata) {
+    let is_dir = metadata.is_dir();
+    let name = relative.rsplit('/').next().unwrap_or_default();
+
+    let _ = write!(
+        xml,
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>",
+        href_for(relative, is_dir),
+        escape_xml(name)
+    );
+
+    if is_dir {
+        xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        let content_type = from_path(relative).first_or_octet_stream();
+        let _ = write!(
+            xml,
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>{}</D:getcontenttype>",
+            metadata.len(),
+            escape_xml(content_type.as_ref())
+        );
+    }
+
+    if let Ok(modified) = metadata.modified() {
+        let _ = write!(
+            xml,
+            "<D:getlastmodified>{}</D:getlastmodified>",
+            httpdate::fmt_http_date(modified)
+        );
+    }
+
+    xml.push_str(
+        "<D:supportedlock><D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+<D:locktype><D:write/></D:locktype></D:lockentry></D:supportedlock>",
+    );
+    xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+}
+
+fn href_for(relative: &str, is_dir: bool) -> String {
+    let mut href = String::from(DAV_PREFIX);
+    href.push('/');
+    for (index, segment) in relative.split('/').filter(|s| !s.is_empty()).enumerate() {
+        if index > 0 {
+            href.push('/');
+        }
+        href.push_str(&utf8_percent_encode(segment, HREF_SEGMENT).to_string());
+    }
+    if is_dir && !href.ends_with('/') {
+        href.push('/');
+    }
+    escape_xml(&href)
+}
+
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn destination_path(destination: &str) -> Option<String> {
+    // Destination is usually an absolute URL; keep only its path component
+    let path = match destination.find("://") {
+        Some(scheme_end) => {
+            let rest = &destination[scheme_end + 3..];
+            &rest[rest.find('/')?..]
+        }
+        None => destination,
+    };
+
+    let path = path.strip_prefix(DAV_PREFIX)?;
+    if !path.is_empty() && !path.starts_with('/') {
+        return None;
+    }
+
+    Some(percent_decode_str(path).decode_utf8_lossy().into_owned())
+}
+
+async fn parent_exists(path: &Path) -> bool {
+    match path.parent() {
+        Some(parent) => fs::metadata(parent)
+            .await
+            .map(|metadata| metadata.is_dir())
+            .unwrap_or(false),
+        None => false,
+    }
+}
+
+fn copy_path(from: &Path, to: &Path, visible: impl Fn(&Path) -> bool) -> std::io::Result<()> {
+    if !from.is_dir() {
+        return std::fs::copy(from, to).map(|_| ());
+    }
+
+    for entry in WalkDir::new(from)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|entry| !is_internal(entry.path()) && visible(entry.path()))
+    {
+        let entry = entry.map_err(std::io::Error::other)?;
+        let relative = entry
+            .path()
+            .strip_prefix(from)
+            .map_err(std::io::Error::other)?;
+        let target = to.join(relative);
+
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else if entry.file_type().is_file() {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn multistatus_response(xml: String) -> warp::reply::Response {
+    warp::http::Response::builder()
+        .status(StatusCode::MULTI_STATUS)
+        .header("content-type", "application/xml; charset=utf-8")
+        .body(Body::from(xml))
+        .unwrap()
+}
+
+fn status_response(status: StatusCode) -> warp::reply::Response {
+    warp::http::Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
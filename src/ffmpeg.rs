@@ -0,0 +1,137 @@
+//! Frame extraction through an external `ffmpeg` binary, used for video
+//! previews when one is installed.
+
+use image::DynamicImage;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest an ffmpeg run may take before it is killed; a broken or hostile
+/// file must not tie up a preview worker forever
+const TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+static FFMPEG: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// The `ffmpeg` binary from `FFMPEG` or the `PATH`, if it runs.
+pub fn binary() -> Option<&'static Path> {
+    FFMPEG
+        .get_or_init(|| {
+            let path = std::env::var_os("FFMPEG")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("ffmpeg"));
+            let mut command = Command::new(&path);
+            command
+                .arg("-version")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+            let runs = run(command).is_ok_and(|output| output.status.success());
+            runs.then_some(path)
+        })
+        .as_deref()
+}
+
+/// Length of a video in seconds, read from the `Duration:` line ffmpeg
+/// prints for its input. `None` for streams without a known length.
+pub fn duration(source: &Path) -> io::Result<Option<f64>> {
+    let ffmpeg = binary().ok_or(io::ErrorKind::NotFound)?;
+    // Without an output ffmpeg only describes the input and exits with an error
+    let mut command = Command::new(ffmpeg);
+    command
+        .arg("-hide_banner")
+        .arg("-i")
+        .arg(source)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let output = run(command)?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let duration = stderr
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Duration: "))
+        .and_then(|rest| rest.split(',').next())
+        .and_then(|time| {
+            let mut seconds = 0.0;
+            for part in time.split(':') {
+                seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+            }
+            Some(seconds)
+        });
+    Ok(duration)
+}
+
+/// Decodes the frame at `seconds`, scaled down to fit `size` x `size`.
+/// Rotation metadata is applied by ffmpeg itself.
+pub fn frame(source: &Path, seconds: f64, size: u32) -> io::Result<DynamicImage> {
+    let ffmpeg = binary().ok_or(io::ErrorKind::NotFound)?;
+    let scale = format!(
+        "scale='min({size},iw)':'min({size},ih)':force_original_aspect_ratio=decrease",
+        size = size
+    );
+    let mut command = Command::new(ffmpeg);
+    command
+        .args(["-v", "error", "-ss"])
+        .arg(format!("{:.3}", seconds))
+        .arg("-i")
+        .arg(source)
+        .args(["-frames:v", "1", "-an", "-vf"])
+        .arg(scale)
+        .args(["-f", "image2pipe", "-c:v", "png", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    let output = run(command)?;
+
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(io::Error::other("ffmpeg produced no frame"));
+    }
+    image::load_from_memory_with_format(&output.stdout, image::ImageFormat::Png)
+        .map_err(io::Error::other)
+}
+
+/// Like `Command::output`, but kills the process once it runs past
+/// `TIMEOUT`. Only the streams set to `Stdio::piped()` are collected.
+fn run(mut command: Command) -> io::Result<Output> {
+    let mut child = command.spawn()?;
+    let collect = |stream: Option<Box<dyn Read + Send>>| {
+        stream.map(|mut stream| {
+            thread::spawn(move || {
+                let mut buffer = Vec::new();
+                stream.read_to_end(&mut buffer).map(|_| buffer)
+            })
+        })
+    };
+    let stdout = collect(child.stdout.take().map(|s| Box::new(s) as _));
+    let stderr = collect(child.stderr.take().map(|s| Box::new(s) as _));
+
+    let deadline = Instant::now() + TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            // The readers finish once the pipes close with the process
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "ffmpeg timed out"));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let join = |reader: Option<thread::JoinHandle<io::Result<Vec<u8>>>>| match reader {
+        Some(reader) => reader
+            .join()
+            .map_err(|_| io::Error::other("ffmpeg reader panicked"))?,
+        None => Ok(Vec::new()),
+    };
+    Ok(Output {
+        status,
+        stdout: join(stdout)?,
+        stderr: join(stderr)?,
+    })
+}
@@ -1,16 +1,33 @@
+use std::collections::HashMap;
 use std::net::Ipv4Addr;
-use warp::Filter;
+use warp::{Filter, Reply};
 
+mod acl;
+mod archive;
+mod auth;
 mod endpoints;
+mod ffmpeg;
+mod metadata;
+mod store;
+mod thumbnail;
 mod types;
+mod zipstream;
 
+use endpoints::compress::CompressRequest;
 use endpoints::download_bulk::DownloadBulkRequest;
+use endpoints::dropbox::CreateDropBoxRequest;
+use endpoints::extract::ExtractRequest;
 use endpoints::mv::MvItem;
+use endpoints::shares::CreateShareRequest;
 use endpoints::{
-    handle_delete, handle_download, handle_downloads, handle_file_server, handle_list,
-    handle_mkdir, handle_mv, handle_save, handle_search, handle_upload, ui_routes,
+    handle_capabilities, handle_compress, handle_create_dropbox, handle_create_share,
+    handle_delete, handle_download, handle_downloads, handle_dropbox_page, handle_dropbox_upload,
+    handle_extract, handle_file_server, handle_job, handle_list, handle_list_dropboxes,
+    handle_list_jobs, handle_list_shares, handle_meta, handle_mkdir, handle_mv,
+    handle_revoke_dropbox, handle_revoke_share, handle_save, handle_search, handle_share,
+    handle_thumb, handle_tus, handle_upload, handle_webdav, read_only_response, ui_routes,
 };
-use types::{data_dir, FileQuery, ListQuery, SearchQuery};
+use types::{data_dir, read_only, FileQuery, ListQuery, SearchQuery, ThumbQuery, UploadQuery};
 
 const PORT: u16 = 30003;
 const BIND_ADDR: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 1);
@@ -67,15 +84,46 @@ async fn shutdown_signal() {
     println!("Shutdown signal received, stopping server gracefully...");
 }
 
+fn hash_password_command() {
+    let mut password = String::new();
+    if std::io::stdin().read_line(&mut password).is_err() {
+        eprintln!("Failed to read password from stdin");
+        std::process::exit(1);
+    }
+
+    match auth::hash_password(password.trim_end_matches(['\r', '\n'])) {
+        Ok(hash) => println!("{}", hash),
+        Err(e) => {
+            eprintln!("Failed to hash password: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Matches GET and HEAD requests, extracting whether it is a HEAD.
+fn get_or_head() -> impl Filter<Extract = (bool,), Error = warp::Rejection> + Clone {
+    warp::get()
+        .map(|| false)
+        .or(warp::head().map(|| true))
+        .unify()
+}
+
 #[tokio::main]
 async fn main() {
+    if std::env::args().nth(1).as_deref() == Some("hash-password") {
+        hash_password_command();
+        return;
+    }
+
     let bind_addr = get_bind_addr();
     let port = get_port();
 
     let api_download = warp::path("api")
         .and(warp::path("download"))
-        .and(warp::get())
         .and(warp::path::tail())
+        .and(get_or_head())
+        .and(warp::header::headers_cloned())
+        .and(auth::authenticated())
         .and_then(handle_download);
 
     let api_downloads = warp::path("api")
@@ -83,37 +131,60 @@ async fn main() {
         .and(warp::post())
         .and(warp::body::content_length_limit(1024 * 1024))
         .and(warp::body::json::<DownloadBulkRequest>())
+        .and(auth::authenticated())
         .and_then(handle_downloads);
 
     let api_upload = warp::path("api")
         .and(warp::path("upload"))
         .and(warp::post())
-        .and(warp::query::<ListQuery>())
+        .and(warp::query::<UploadQuery>())
         .and(warp::multipart::form().max_length(1024 * 1024 * 1024 * 256)) // 256GB limit
+        .and(auth::authenticated())
         .and_then(handle_upload);
 
     let api_list = warp::path("api")
         .and(warp::path("list"))
         .and(warp::get())
         .and(warp::query::<ListQuery>())
+        .and(warp::header::headers_cloned())
+        .and(auth::authenticated())
         .and_then(handle_list);
 
     let api_search = warp::path("api")
         .and(warp::path("search"))
         .and(warp::get())
         .and(warp::query::<SearchQuery>())
+        .and(warp::header::headers_cloned())
+        .and(auth::authenticated())
         .and_then(handle_search);
 
+    let api_thumb = warp::path("api")
+        .and(warp::path("thumb"))
+        .and(warp::get())
+        .and(warp::query::<ThumbQuery>())
+        .and(warp::header::headers_cloned())
+        .and(auth::authenticated())
+        .and_then(handle_thumb);
+
+    let api_meta = warp::path("api")
+        .and(warp::path("meta"))
+        .and(warp::get())
+        .and(warp::query::<FileQuery>())
+        .and(auth::authenticated())
+        .and_then(handle_meta);
+
     let api_delete = warp::path("api")
         .and(warp::path("delete"))
         .and(warp::delete())
         .and(warp::query::<FileQuery>())
+        .and(auth::authenticated())
         .and_then(handle_delete);
 
     let api_mkdir = warp::path("api")
         .and(warp::path("mkdir"))
         .and(warp::post())
         .and(warp::query::<FileQuery>())
+        .and(auth::authenticated())
         .and_then(handle_mkdir);
 
     let api_save = warp::path("api")
@@ -121,6 +192,7 @@ async fn main() {
         .and(warp::post())
         .and(warp::query::<FileQuery>())
         .and(warp::body::bytes())
+        .and(auth::authenticated())
         .and_then(handle_save);
 
     let api_mv = warp::path("api")
@@ -128,30 +200,230 @@ async fn main() {
         .and(warp::post())
         .and(warp::body::content_length_limit(1024 * 1024))
         .and(warp::body::json::<Vec<MvItem>>())
+        .and(auth::authenticated())
         .and_then(handle_mv);
 
+    let api_extract = warp::path("api")
+        .and(warp::path("extract"))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::json::<ExtractRequest>())
+        .and(auth::authenticated())
+        .and_then(handle_extract);
+
+    let api_compress = warp::path("api")
+        .and(warp::path("compress"))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::json::<CompressRequest>())
+        .and(auth::authenticated())
+        .and_then(handle_compress);
+
+    let api_jobs_list = warp::path("api")
+        .and(warp::path("jobs"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::authenticated())
+        .and_then(handle_list_jobs);
+
+    let api_job = warp::path("api")
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::authenticated())
+        .and_then(handle_job);
+
+    let webdav = warp::path("dav")
+        .and(warp::method())
+        .and(warp::path::tail())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::stream())
+        .and(auth::authenticated())
+        .and_then(handle_webdav);
+
+    // Resumable uploads; the tus protocol uses its own methods and headers
+    let api_tus = warp::path("api")
+        .and(warp::path("tus"))
+        .and(warp::method())
+        .and(warp::path::tail())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::stream())
+        .and(auth::authenticated())
+        .and_then(handle_tus);
+
+    let api_shares_create = warp::path("api")
+        .and(warp::path("shares"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::json::<CreateShareRequest>())
+        .and(auth::authenticated())
+        .and_then(handle_create_share);
+
+    let api_shares_list = warp::path("api")
+        .and(warp::path("shares"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::authenticated())
+        .and_then(handle_list_shares);
+
+    let api_shares_revoke = warp::path("api")
+        .and(warp::path("shares"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(auth::authenticated())
+        .and_then(handle_revoke_share);
+
+    // Share links carry their own token (and optional password) instead of user auth
+    let share = warp::path("s")
+        .and(warp::path::param::<String>())
+        .and(warp::path::tail())
+        .and(warp::path::full())
+        .and(get_or_head())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and_then(handle_share);
+
+    let api_dropboxes_create = warp::path("api")
+        .and(warp::path("dropboxes"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::json::<CreateDropBoxRequest>())
+        .and(auth::authenticated())
+        .and_then(handle_create_dropbox);
+
+    let api_dropboxes_list = warp::path("api")
+        .and(warp::path("dropboxes"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth::authenticated())
+        .and_then(handle_list_dropboxes);
+
+    let api_dropboxes_revoke = warp::path("api")
+        .and(warp::path("dropboxes"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(auth::authenticated())
+        .and_then(handle_revoke_dropbox);
+
+    // Upload-only links: a page and a multipart endpoint, nothing else
+    let dropbox_page = warp::path("u")
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(handle_dropbox_page);
+
+    let dropbox_upload = warp::path("u")
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::multipart::form().max_length(1024 * 1024 * 1024 * 256)) // 256GB limit
+        .and_then(handle_dropbox_upload);
+
+    let api_capabilities = warp::path("api")
+        .and(warp::path("capabilities"))
+        .and(warp::get())
+        .and_then(handle_capabilities);
+
+    // In read-only mode the mutating routes are replaced by a 405 responder
+    let mutating_routes = if read_only() {
+        warp::path("api")
+            .and(
+                warp::path("upload")
+                    .or(warp::path("save"))
+                    .unify()
+                    .or(warp::path("mkdir"))
+                    .unify()
+                    .or(warp::path("mv"))
+                    .unify()
+                    .or(warp::path("delete"))
+                    .unify()
+                    .or(warp::path("extract"))
+                    .unify()
+                    .or(warp::path("compress"))
+                    .unify(),
+            )
+            .map(read_only_response)
+            .boxed()
+    } else {
+        api_upload
+            .or(api_delete)
+            .or(api_mkdir)
+            .or(api_save)
+            .or(api_mv)
+            .or(api_extract)
+            .or(api_compress)
+            .map(Reply::into_response)
+            .boxed()
+    };
+
     let favicon = warp::path("favicon.ico").and(warp::get()).map(|| "");
 
     let file_server = warp::path::tail()
+        .and(get_or_head())
+        .and(warp::query::<HashMap<String, String>>())
         .and(warp::header::headers_cloned())
+        .and(auth::authenticated())
         .and_then(handle_file_server);
 
-    let routes = ui_routes()
-        .or(api_download)
-        .or(api_downloads)
-        .or(api_upload)
-        .or(api_list)
-        .or(api_search)
-        .or(api_delete)
-        .or(api_mkdir)
-        .or(api_save)
-        .or(api_mv)
-        .or(favicon)
-        .or(file_server);
+    let routes = share
+        .or(dropbox_page)
+        .or(dropbox_upload)
+        .or(auth::required().and(
+            ui_routes()
+                .or(api_download)
+                .or(api_downloads)
+                .or(api_list)
+                .or(api_search)
+                .or(api_thumb)
+                .or(api_meta)
+                .or(api_capabilities)
+                .or(api_shares_create)
+                .or(api_shares_list)
+                .or(api_shares_revoke)
+                .or(api_dropboxes_create)
+                .or(api_dropboxes_list)
+                .or(api_dropboxes_revoke)
+                .or(api_jobs_list)
+                .or(api_job)
+                .or(mutating_routes)
+                .or(api_tus)
+                .or(webdav)
+                .or(favicon)
+                .or(file_server),
+        ));
+    let routes = routes.recover(auth::handle_rejection);
 
     println!("Server starting on http://{}:{}", bind_addr, port);
     println!("UI available at: http://{}:{}/ui", bind_addr, port);
+    println!("WebDAV available at: http://{}:{}/dav/", bind_addr, port);
     println!("Serving files from: {}", data_dir().display());
+    match auth::auth_config() {
+        Some(config) => println!(
+            "Authentication enabled: {} user(s), {} token(s)",
+            config.users.len(),
+            config.tokens.len()
+        ),
+        None => println!("Authentication disabled (set AUTH_FILE to enable)"),
+    }
+    if read_only() {
+        println!("Read-only mode enabled");
+    }
+    match ffmpeg::binary() {
+        Some(path) => println!("Video thumbnails enabled: {}", path.display()),
+        None => println!("Video thumbnails disabled (ffmpeg not found, set FFMPEG to its path)"),
+    }
+    if let Some(config) = acl::acl_config() {
+        println!("Access control enabled: {} rule(s)", config.rules.len());
+    }
+
+    tokio::spawn(endpoints::tus::collect_stale_uploads());
+    tokio::spawn(thumbnail::collect_cache());
 
     warp::serve(routes)
         .bind_with_graceful_shutdown((bind_addr.octets(), port), shutdown_signal())
@@ -0,0 +1,117 @@
+use super::AudioMeta;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use symphonia::core::codecs::{self, CodecType};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision};
+use symphonia::core::probe::Hint;
+
+const CODEC_NAMES: &[(CodecType, &str)] = &[
+    (codecs::CODEC_TYPE_MP1, "mp1"),
+    (codecs::CODEC_TYPE_MP2, "mp2"),
+    (codecs::CODEC_TYPE_MP3, "mp3"),
+    (codecs::CODEC_TYPE_AAC, "aac"),
+    (codecs::CODEC_TYPE_VORBIS, "vorbis"),
+    (codecs::CODEC_TYPE_OPUS, "opus"),
+    (codecs::CODEC_TYPE_FLAC, "flac"),
+    (codecs::CODEC_TYPE_ALAC, "alac"),
+    (codecs::CODEC_TYPE_WAVPACK, "wavpack"),
+    (codecs::CODEC_TYPE_PCM_U8, "pcm"),
+    (codecs::CODEC_TYPE_PCM_S16LE, "pcm"),
+    (codecs::CODEC_TYPE_PCM_S24LE, "pcm"),
+    (codecs::CODEC_TYPE_PCM_S32LE, "pcm"),
+    (codecs::CODEC_TYPE_PCM_F32LE, "pcm"),
+    (codecs::CODEC_TYPE_PCM_F64LE, "pcm"),
+];
+
+/// Reads tags and stream details from the container headers without
+/// decoding any audio.
+pub fn read(path: &Path) -> io::Result<AudioMeta> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let mut hint = Hint::new();
+    hint.with_extension(&extension);
+
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(io::Error::other)?;
+
+    let mut meta = AudioMeta {
+        format: match extension.as_str() {
+            "ogg" | "oga" | "opus" => "ogg",
+            "m4a" => "mp4",
+            "aac" => "adts",
+            "mp3" => "mp3",
+            "flac" => "flac",
+            _ => "wav",
+        },
+        ..Default::default()
+    };
+
+    // Tags may come before the container (ID3v2 in front of MP3 or FLAC) or
+    // inside it (Vorbis comments, MP4 atoms); later ones win
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|log| log.current()) {
+        collect_tags(revision, &mut meta.tags);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        collect_tags(revision, &mut meta.tags);
+    }
+
+    if let Some(track) = probed.format.default_track() {
+        let params = &track.codec_params;
+        meta.codec = CODEC_NAMES
+            .iter()
+            .find(|(codec, _)| *codec == params.codec)
+            .map(|(_, name)| *name);
+        meta.sample_rate = params.sample_rate;
+        meta.channels = params.channels.map(|channels| channels.count() as u32);
+        meta.bits_per_sample = params.bits_per_sample;
+        meta.duration = match (params.n_frames, params.time_base) {
+            (Some(frames), Some(time_base)) if time_base.numer > 0 && time_base.denom > 0 => {
+                let time = time_base.calc_time(frames);
+                Some(time.seconds as f64 + time.frac)
+            }
+            (Some(frames), _) => params
+                .sample_rate
+                .filter(|rate| *rate > 0)
+                .map(|rate| frames as f64 / rate as f64),
+            _ => None,
+        };
+    }
+    Ok(meta)
+}
+
+/// Adds the tags with a standard meaning, keyed in snake case like
+/// `track_title` or `album_artist`.
+fn collect_tags(revision: &MetadataRevision, tags: &mut BTreeMap<String, String>) {
+    for tag in revision.tags() {
+        let Some(key) = tag.std_key else {
+            continue;
+        };
+        let value = tag.value.to_string();
+        if value.trim().is_empty() {
+            continue;
+        }
+
+        let mut name = String::new();
+        for (index, c) in format!("{:?}", key).chars().enumerate() {
+            if c.is_ascii_uppercase() && index > 0 {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        }
+        tags.insert(name, value.trim().to_string());
+    }
+}
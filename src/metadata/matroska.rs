@@ -0,0 +1,240 @@
+//! Just enough of Matroska and WebM to list their tracks: the EBML header,
+//! segment info and tracks are read, clusters of media data are not.
+
+use super::{ContainerMeta, Stream};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const EBML: u32 = 0x1A45DFA3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const TITLE: u32 = 0x7BA9;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const NAME: u32 = 0x536E;
+const LANGUAGE: u32 = 0x22B59C;
+const LANGUAGE_BCP47: u32 = 0x22B59D;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const CLUSTER: u32 = 0x1F43B675;
+
+/// Info and Tracks are a few kilobytes; anything far larger is corrupt.
+const MAX_ELEMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+pub fn read(path: &Path) -> io::Result<ContainerMeta> {
+    let mut file = BufReader::new(File::open(path)?);
+
+    let (id, size) = element_header(&mut file)?;
+    if id != EBML {
+        return Err(invalid("not an EBML file"));
+    }
+    let header = read_body(&mut file, size)?;
+    let doc_type = children(&header)
+        .find(|(id, _)| *id == DOC_TYPE)
+        .map(|(_, value)| string(value));
+    let mut meta = ContainerMeta {
+        format: match doc_type.as_deref() {
+            Some("webm") => "webm",
+            Some("matroska") => "matroska",
+            _ => return Err(invalid("unknown EBML document type")),
+        },
+        ..Default::default()
+    };
+
+    let (id, _) = element_header(&mut file)?;
+    if id != SEGMENT {
+        return Err(invalid("no segment"));
+    }
+
+    // Segment children follow one another; stop once media data begins or
+    // both elements of interest have been seen
+    let mut scale = 1_000_000u64;
+    let mut duration = None;
+    let mut seen_info = false;
+    let mut seen_tracks = false;
+    while !(seen_info && seen_tracks) {
+        let Ok((id, size)) = element_header(&mut file) else {
+            break;
+        };
+        match id {
+            INFO => {
+                seen_info = true;
+                for (id, value) in children(&read_body(&mut file, size)?) {
+                    match id {
+                        TIMESTAMP_SCALE => {
+                            scale = uint(value).filter(|scale| *scale > 0).unwrap_or(scale)
+                        }
+                        DURATION => duration = float(value),
+                        TITLE => meta.title = Some(string(value)).filter(|title| !title.is_empty()),
+                        _ => {}
+                    }
+                }
+            }
+            TRACKS => {
+                seen_tracks = true;
+                meta.streams = children(&read_body(&mut file, size)?)
+                    .filter(|(id, _)| *id == TRACK_ENTRY)
+                    .map(|(_, entry)| parse_track(entry))
+                    .collect();
+            }
+            CLUSTER => break,
+            _ => {
+                let size = size.ok_or_else(|| invalid("element of unknown size"))?;
+                file.seek(SeekFrom::Current(size as i64))?;
+            }
+        }
+    }
+
+    meta.duration = duration
+        .filter(|duration| *duration > 0.0)
+        .map(|duration| duration * scale as f64 / 1e9);
+    Ok(meta)
+}
+
+fn parse_track(entry: &[u8]) -> Stream {
+    let mut stream = Stream {
+        kind: "other",
+        // Matroska's default when a track has no Language element
+        language: Some("eng".to_string()),
+        ..Default::default()
+    };
+    let mut bcp47 = None;
+
+    for (id, value) in children(entry) {
+        match id {
+            TRACK_TYPE => {
+                stream.kind = match uint(value) {
+                    Some(1) => "video",
+                    Some(2) => "audio",
+                    Some(17) => "subtitle",
+                    _ => "other",
+                }
+            }
+            CODEC_ID => stream.codec = Some(string(value)),
+            NAME => stream.name = Some(string(value)).filter(|name| !name.is_empty()),
+            LANGUAGE => stream.language = Some(string(value)),
+            LANGUAGE_BCP47 => bcp47 = Some(string(value)),
+            VIDEO => {
+                for (id, value) in children(value) {
+                    match id {
+                        PIXEL_WIDTH => stream.width = uint(value).map(|width| width as u32),
+                        PIXEL_HEIGHT => stream.height = uint(value).map(|height| height as u32),
+                        _ => {}
+                    }
+                }
+            }
+            AUDIO => {
+                // Sampling frequency defaults to 8 kHz and channels to 1
+                stream.sample_rate = Some(8000);
+                stream.channels = Some(1);
+                for (id, value) in children(value) {
+                    match id {
+                        SAMPLING_FREQUENCY => {
+                            stream.sample_rate = float(value).map(|rate| rate as u32)
+                        }
+                        CHANNELS => stream.channels = uint(value).map(|channels| channels as u32),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    stream.language = bcp47
+        .or(stream.language)
+        .filter(|language| !language.is_empty() && language != "und");
+    stream
+}
+
+/// Reads an element ID and its data size; `None` for an unknown size.
+fn element_header(reader: &mut impl Read) -> io::Result<(u32, Option<u64>)> {
+    let (id, _) = vint(reader, true)?;
+    let (size, unknown) = vint(reader, false)?;
+    Ok((id as u32, (!unknown).then_some(size)))
+}
+
+/// Reads a variable-length integer. IDs keep their length marker; sizes drop
+/// it and report whether all value bits were set, which means "unknown".
+fn vint(reader: &mut impl Read, keep_marker: bool) -> io::Result<(u64, bool)> {
+    let mut first = [0u8; 1];
+    reader.read_exact(&mut first)?;
+    let len = first[0].leading_zeros() as usize + 1;
+    if len > 8 || (keep_marker && len > 4) {
+        return Err(invalid("invalid variable-length integer"));
+    }
+
+    let mask = 0xFFu64 >> len;
+    let mut value = if keep_marker {
+        first[0] as u64
+    } else {
+        first[0] as u64 & mask
+    };
+    let mut all_ones = value == mask;
+    for _ in 1..len {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        value = (value << 8) | byte[0] as u64;
+        all_ones &= byte[0] == 0xFF;
+    }
+    Ok((value, !keep_marker && all_ones))
+}
+
+fn read_body(reader: &mut impl Read, size: Option<u64>) -> io::Result<Vec<u8>> {
+    let size = size
+        .filter(|size| *size <= MAX_ELEMENT_SIZE)
+        .ok_or_else(|| invalid("element too large"))?;
+    let mut body = vec![0u8; size as usize];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// Iterates over the child elements in `data` as `(id, body)`, stopping at
+/// the first malformed or unknown-sized one.
+fn children(data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        let mut reader = rest;
+        let (id, size) = element_header(&mut reader).ok()?;
+        let size = usize::try_from(size?).ok()?;
+        let body = reader.get(..size)?;
+        rest = &reader[size..];
+        Some((id, body))
+    })
+}
+
+fn uint(value: &[u8]) -> Option<u64> {
+    (value.len() <= 8).then(|| {
+        value
+            .iter()
+            .fold(0u64, |number, byte| (number << 8) | *byte as u64)
+    })
+}
+
+fn float(value: &[u8]) -> Option<f64> {
+    match value.len() {
+        4 => Some(f32::from_be_bytes(value.try_into().ok()?) as f64),
+        8 => Some(f64::from_be_bytes(value.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+fn string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
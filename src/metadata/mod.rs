@@ -0,0 +1,192 @@
+//! Structured details about media files: EXIF for photos, tags and duration
+//! for audio, and container and stream information for videos.
+
+mod audio;
+mod matroska;
+mod mp4;
+mod photo;
+
+use crate::types::has_extension;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+/// Parsed files remembered at once; beyond this an arbitrary entry is dropped.
+const CACHE_CAPACITY: usize = 4096;
+
+const PHOTO_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "webp", "gif", "tif", "tiff", "heic", "heif",
+];
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "flac", "ogg", "oga", "opus", "m4a", "aac", "wav"];
+const MP4_EXTENSIONS: &[&str] = &["mp4", "m4v", "mov"];
+const MATROSKA_EXTENSIONS: &[&str] = &["mkv", "webm"];
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum MediaMeta {
+    Image(ImageMeta),
+    Audio(AudioMeta),
+    Video(ContainerMeta),
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct ImageMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_make: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lens: Option<String>,
+    /// Shutter speed as written on cameras, e.g. `1/250`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exposure_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub f_number: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iso: Option<u32>,
+    /// Millimetres
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focal_length: Option<f64>,
+    /// Local time from the camera, with its UTC offset when recorded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taken_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps: Option<Gps>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<u32>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Gps {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Metres above sea level
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub altitude: Option<f64>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct AudioMeta {
+    pub format: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<&'static str>,
+    /// Seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bits_per_sample: Option<u32>,
+    /// Standard tags such as `track_title`, `artist` and `album`
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct ContainerMeta {
+    pub format: &'static str,
+    /// Seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub streams: Vec<Stream>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct Stream {
+    /// `video`, `audio`, `subtitle` or `other`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// Codec identifier as stored, e.g. `avc1` in MP4 or `V_VP9` in Matroska
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+struct CachedMeta {
+    modified: Option<SystemTime>,
+    len: u64,
+    meta: MediaMeta,
+}
+
+static CACHE: OnceLock<Mutex<HashMap<PathBuf, CachedMeta>>> = OnceLock::new();
+
+fn cache() -> &'static Mutex<HashMap<PathBuf, CachedMeta>> {
+    CACHE.get_or_init(Default::default)
+}
+
+/// Whether `read` knows how to describe the file at `path`.
+pub fn supported(path: &Path) -> bool {
+    [
+        PHOTO_EXTENSIONS,
+        AUDIO_EXTENSIONS,
+        MP4_EXTENSIONS,
+        MATROSKA_EXTENSIONS,
+    ]
+    .iter()
+    .any(|known| has_extension(path, known))
+}
+
+/// Describes the file at `path`, reusing the previous result while the file's
+/// modification time and size are unchanged.
+pub fn read(path: &Path, metadata: &std::fs::Metadata) -> io::Result<MediaMeta> {
+    let modified = metadata.modified().ok();
+    let len = metadata.len();
+    if let Some(cached) = cache().lock().unwrap().get(path) {
+        if cached.modified == modified && cached.len == len {
+            return Ok(cached.meta.clone());
+        }
+    }
+
+    let meta = parse(path)?;
+
+    let mut cache = cache().lock().unwrap();
+    if cache.len() >= CACHE_CAPACITY && !cache.contains_key(path) {
+        if let Some(evicted) = cache.keys().next().cloned() {
+            cache.remove(&evicted);
+        }
+    }
+    cache.insert(
+        path.to_path_buf(),
+        CachedMeta {
+            modified,
+            len,
+            meta: meta.clone(),
+        },
+    );
+    Ok(meta)
+}
+
+fn parse(path: &Path) -> io::Result<MediaMeta> {
+    if has_extension(path, PHOTO_EXTENSIONS) {
+        photo::read(path).map(MediaMeta::Image)
+    } else if has_extension(path, AUDIO_EXTENSIONS) {
+        audio::read(path).map(MediaMeta::Audio)
+    } else if has_extension(path, MP4_EXTENSIONS) {
+        mp4::read(path).map(MediaMeta::Video)
+    } else if has_extension(path, MATROSKA_EXTENSIONS) {
+        matroska::read(path).map(MediaMeta::Video)
+    } else {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
@@ -0,0 +1,249 @@
+//! Just enough of ISO base media files (MP4, M4V, QuickTime) to list their
+//! tracks: the `moov` box is read into memory and walked, media data is
+//! skipped.
+
+use super::{ContainerMeta, Stream};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Larger `moov` boxes are refused rather than read into memory.
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+pub fn read(path: &Path) -> io::Result<ContainerMeta> {
+    let mut file = BufReader::new(File::open(path)?);
+    let file_len = file.get_ref().metadata()?.len();
+    let mut format = "mp4";
+
+    let mut position = 0;
+    while position < file_len {
+        file.seek(SeekFrom::Start(position))?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+        let kind: [u8; 4] = header[4..8].try_into().unwrap();
+        let (size, header_len) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => (file_len - position, 8),
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large)?;
+                (u64::from_be_bytes(large), 16)
+            }
+            size => (size as u64, 8),
+        };
+        if size < header_len {
+            return Err(invalid("box smaller than its header"));
+        }
+
+        match &kind {
+            b"ftyp" => {
+                let mut brand = [0u8; 4];
+                file.read_exact(&mut brand)?;
+                if &brand == b"qt  " {
+                    format = "quicktime";
+                }
+            }
+            b"moov" => {
+                let body_len = size - header_len;
+                if body_len > MAX_MOOV_SIZE {
+                    return Err(invalid("moov box too large"));
+                }
+                let mut body = vec![0u8; body_len as usize];
+                file.read_exact(&mut body)?;
+                let mut meta = parse_moov(&body)?;
+                meta.format = format;
+                return Ok(meta);
+            }
+            _ => {}
+        }
+        position = position.saturating_add(size);
+    }
+    Err(invalid("no moov box"))
+}
+
+fn parse_moov(moov: &[u8]) -> io::Result<ContainerMeta> {
+    let mut meta = ContainerMeta::default();
+    for (kind, body) in boxes(moov) {
+        match kind {
+            b"mvhd" => {
+                let mut reader = Cursor::new(body);
+                let version = reader.u8()?;
+                reader.skip(3 + if version == 1 { 16 } else { 8 })?;
+                let timescale = reader.u32()?;
+                let duration = if version == 1 {
+                    reader.u64()?
+                } else {
+                    reader.u32()? as u64
+                };
+                meta.duration = seconds(duration, timescale);
+            }
+            b"trak" => meta.streams.push(parse_trak(body)?),
+            _ => {}
+        }
+    }
+    Ok(meta)
+}
+
+fn parse_trak(trak: &[u8]) -> io::Result<Stream> {
+    let mut stream = Stream {
+        kind: "other",
+        ..Default::default()
+    };
+    let mut handler = [0u8; 4];
+
+    for (kind, body) in boxes(trak) {
+        match kind {
+            b"tkhd" => {
+                let mut reader = Cursor::new(body);
+                let version = reader.u8()?;
+                // Times, track id, reserved, duration, then reserved, layer,
+                // group, volume, reserved and the matrix
+                reader.skip(3 + if version == 1 { 32 } else { 20 } + 52)?;
+                let width = reader.u32()? >> 16;
+                let height = reader.u32()? >> 16;
+                if width > 0 && height > 0 {
+                    stream.width = Some(width);
+                    stream.height = Some(height);
+                }
+            }
+            b"mdia" => {
+                for (kind, body) in boxes(body) {
+                    match kind {
+                        b"mdhd" => stream.language = mdhd_language(body),
+                        b"hdlr" => {
+                            if let Some(handler_type) = body.get(8..12) {
+                                handler.copy_from_slice(handler_type);
+                            }
+                        }
+                        b"minf" => {
+                            let stsd = boxes(body)
+                                .filter(|(kind, _)| *kind == b"stbl")
+                                .flat_map(|(_, stbl)| boxes(stbl))
+                                .find(|(kind, _)| *kind == b"stsd");
+                            if let Some((_, stsd)) = stsd {
+                                parse_stsd(stsd, &mut stream);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    stream.kind = match &handler {
+        b"vide" => "video",
+        b"soun" => "audio",
+        b"subt" | b"text" | b"sbtl" => "subtitle",
+        _ => "other",
+    };
+    if stream.kind != "video" {
+        stream.width = None;
+        stream.height = None;
+    }
+    if stream.kind != "audio" {
+        stream.channels = None;
+        stream.sample_rate = None;
+    }
+    Ok(stream)
+}
+
+/// Takes the codec from the first sample description, plus the channel
+/// count and sample rate for audio.
+fn parse_stsd(stsd: &[u8], stream: &mut Stream) {
+    // Version, flags and entry count precede the entries
+    let Some((codec, entry)) = stsd.get(8..).and_then(|entries| boxes(entries).next()) else {
+        return;
+    };
+    let codec = String::from_utf8_lossy(codec).trim().to_string();
+    if !codec.is_empty() {
+        stream.codec = Some(codec);
+    }
+
+    // Audio sample entries: reserved and data reference index, version and
+    // reserved, then channels, sample size, reserved and a 16.16 rate
+    if let (Some(channels), Some(rate)) = (entry.get(16..18), entry.get(24..28)) {
+        let channels = u16::from_be_bytes(channels.try_into().unwrap()) as u32;
+        let rate = u32::from_be_bytes(rate.try_into().unwrap()) >> 16;
+        if channels > 0 && rate > 0 {
+            stream.channels = Some(channels);
+            stream.sample_rate = Some(rate);
+        }
+    }
+}
+
+/// The packed ISO 639-2 code from a media header, e.g. `eng`.
+fn mdhd_language(mdhd: &[u8]) -> Option<String> {
+    let offset = if *mdhd.first()? == 1 { 4 + 28 } else { 4 + 16 };
+    let packed = u16::from_be_bytes(mdhd.get(offset..offset + 2)?.try_into().ok()?);
+    let code: String = [10, 5, 0]
+        .iter()
+        .map(|shift| (((packed >> shift) & 0x1f) as u8 + 0x60) as char)
+        .collect();
+    (code.chars().all(|c| c.is_ascii_lowercase()) && code != "und").then_some(code)
+}
+
+/// Iterates over the boxes in `data` as `(type, body)`, stopping at the first
+/// malformed one.
+fn boxes(data: &[u8]) -> impl Iterator<Item = (&[u8; 4], &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        let size = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as u64;
+        let kind: &[u8; 4] = rest.get(4..8)?.try_into().ok()?;
+        let (size, header_len) = match size {
+            0 => (rest.len() as u64, 8),
+            1 => (u64::from_be_bytes(rest.get(8..16)?.try_into().ok()?), 16),
+            size => (size, 8),
+        };
+        if size < header_len || size > rest.len() as u64 {
+            return None;
+        }
+        let body = &rest[header_len as usize..size as usize];
+        rest = &rest[size as usize..];
+        Some((kind, body))
+    })
+}
+
+fn seconds(duration: u64, timescale: u32) -> Option<f64> {
+    (timescale > 0 && duration > 0 && duration != u64::MAX && duration != u32::MAX as u64)
+        .then(|| duration as f64 / timescale as f64)
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(invalid("truncated box"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn skip(&mut self, len: usize) -> io::Result<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
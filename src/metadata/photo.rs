@@ -0,0 +1,130 @@
+use super::{Gps, ImageMeta};
+use exif::{DateTime, Exif, In, Rational, Reader, Tag, Value};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+pub fn read(path: &Path) -> io::Result<ImageMeta> {
+    let dimensions = image::image_dimensions(path).ok();
+    let mut meta = ImageMeta {
+        width: dimensions.map(|(width, _)| width),
+        height: dimensions.map(|(_, height)| height),
+        ..Default::default()
+    };
+
+    // Plenty of images carry no EXIF at all, which is only an error when
+    // the file is not a readable image either
+    let mut reader = BufReader::new(File::open(path)?);
+    let exif = match Reader::new().read_from_container(&mut reader) {
+        Ok(exif) => exif,
+        Err(_) if dimensions.is_some() => return Ok(meta),
+        Err(e) => return Err(io::Error::other(e)),
+    };
+
+    meta.width = meta.width.or_else(|| uint(&exif, Tag::PixelXDimension));
+    meta.height = meta.height.or_else(|| uint(&exif, Tag::PixelYDimension));
+    meta.camera_make = text(&exif, Tag::Make);
+    meta.camera_model = text(&exif, Tag::Model);
+    meta.lens = text(&exif, Tag::LensModel);
+    meta.exposure_time = rational(&exif, Tag::ExposureTime).map(|value| {
+        if value.num > 0 && value.num < value.denom {
+            format!("1/{}", (value.denom as f64 / value.num as f64).round())
+        } else {
+            format!("{}", value.to_f64())
+        }
+    });
+    meta.f_number = rational(&exif, Tag::FNumber).map(|value| value.to_f64());
+    meta.iso = uint(&exif, Tag::PhotographicSensitivity);
+    meta.focal_length = rational(&exif, Tag::FocalLength).map(|value| value.to_f64());
+    meta.taken_at = taken_at(&exif);
+    meta.gps = gps(&exif);
+    meta.orientation = uint(&exif, Tag::Orientation);
+    Ok(meta)
+}
+
+fn text(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => {
+            let value = String::from_utf8_lossy(values.first()?);
+            let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+            (!value.is_empty()).then(|| value.to_string())
+        }
+        _ => None,
+    }
+}
+
+fn uint(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+fn rational(exif: &Exif, tag: Tag) -> Option<Rational> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) => values.first().copied().filter(|value| value.denom != 0),
+        _ => None,
+    }
+}
+
+/// `DateTimeOriginal` as ISO 8601, e.g. `2024-05-01T14:03:22+02:00`.
+fn taken_at(exif: &Exif) -> Option<String> {
+    let field = exif
+        .get_field(Tag::DateTimeOriginal, In::PRIMARY)
+        .or_else(|| exif.get_field(Tag::DateTime, In::PRIMARY))?;
+    let Value::Ascii(values) = &field.value else {
+        return None;
+    };
+    let mut time = DateTime::from_ascii(values.first()?).ok()?;
+    if let Some(Value::Ascii(offset)) = exif
+        .get_field(Tag::OffsetTimeOriginal, In::PRIMARY)
+        .map(|field| &field.value)
+    {
+        if let Some(offset) = offset.first() {
+            let _ = time.parse_offset(offset);
+        }
+    }
+
+    let mut formatted = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        time.year, time.month, time.day, time.hour, time.minute, time.second
+    );
+    if let Some(offset) = time.offset {
+        let sign = if offset < 0 { '-' } else { '+' };
+        let offset = offset.unsigned_abs();
+        formatted.push_str(&format!("{}{:02}:{:02}", sign, offset / 60, offset % 60));
+    }
+    Some(formatted)
+}
+
+fn gps(exif: &Exif) -> Option<Gps> {
+    let latitude = coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S")?;
+    let longitude = coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W")?;
+    let altitude = rational(exif, Tag::GPSAltitude).map(|value| {
+        let below_sea_level = uint(exif, Tag::GPSAltitudeRef) == Some(1);
+        if below_sea_level {
+            -value.to_f64()
+        } else {
+            value.to_f64()
+        }
+    });
+    Some(Gps {
+        latitude,
+        longitude,
+        altitude,
+    })
+}
+
+/// Signed decimal degrees from degrees, minutes and seconds plus a
+/// hemisphere reference.
+fn coordinate(exif: &Exif, tag: Tag, reference: Tag, negative: &str) -> Option<f64> {
+    let Value::Rational(parts) = &exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    if parts.iter().any(|part| part.denom == 0) {
+        return None;
+    }
+    let degrees = parts
+        .iter()
+        .zip([1.0, 60.0, 3600.0])
+        .fold(0.0, |sum, (part, unit)| sum + part.to_f64() / unit);
+    let negate = text(exif, reference).is_some_and(|value| value.eq_ignore_ascii_case(negative));
+    Some(if negate { -degrees } else { degrees })
+}
@@ -0,0 +1,57 @@
+use crate::types::state_dir;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// A small list of records persisted as JSON under the state directory.
+/// Every mutation rewrites the whole file, so keep stores small.
+pub struct Store<T> {
+    path: PathBuf,
+    items: Mutex<Vec<T>>,
+}
+
+impl<T: Serialize + DeserializeOwned> Store<T> {
+    pub fn open(name: &str) -> Self {
+        let path = state_dir().join(name);
+        let items = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| match serde_json::from_str(&contents) {
+                Ok(items) => Some(items),
+                Err(e) => {
+                    eprintln!("Ignoring invalid store '{}': {}", path.display(), e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        Self {
+            path,
+            items: Mutex::new(items),
+        }
+    }
+
+    pub fn read<R>(&self, f: impl FnOnce(&[T]) -> R) -> R {
+        f(&self.items.lock().unwrap())
+    }
+
+    pub fn update<R>(&self, f: impl FnOnce(&mut Vec<T>) -> R) -> R {
+        let mut items = self.items.lock().unwrap();
+        let result = f(&mut items);
+        if let Err(e) = self.persist(&items) {
+            eprintln!("Failed to write store '{}': {}", self.path.display(), e);
+        }
+        result
+    }
+
+    fn persist(&self, items: &[T]) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let contents = serde_json::to_vec_pretty(items).map_err(std::io::Error::other)?;
+        let temp_path = self.path.with_extension("tmp");
+        std::fs::write(&temp_path, contents)?;
+        std::fs::rename(&temp_path, &self.path)
+    }
+}
@@ -0,0 +1,399 @@
+//! Downscaled previews of images and videos, cached on disk under the state
+//! directory.
+
+use crate::ffmpeg;
+use crate::types::{has_extension, state_dir, Fit, OutputFormat, ResizeQuery};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImage, ImageDecoder, ImageReader};
+use sha2::{Digest, Sha256};
+use std::fs::Metadata;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Requested sizes are rounded up to one of these to bound the cache.
+const SIZES: &[u32] = &[128, 256, 512, 1024];
+pub const DEFAULT_SIZE: u32 = 256;
+const JPEG_QUALITY: u8 = 80;
+/// Largest width or height a resized image may be requested at
+const MAX_RESIZE: u32 = 4096;
+/// Requested resize widths and heights are rounded up to one of these, so
+/// arbitrary values cannot fill the cache with near-identical renders.
+const RESIZE_SIZES: &[u32] = &[
+    32, 64, 128, 256, 320, 480, 640, 800, 1024, 1280, 1600, 1920, 2560, 3200, 3840, MAX_RESIZE,
+];
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif"];
+const VIDEO_EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mov", "mkv", "webm", "avi", "wmv", "flv", "ogv", "mpg", "mpeg", "ts", "3gp",
+];
+/// Frames in a video sprite sheet, laid out left to right
+pub const SPRITE_FRAMES: u32 = 10;
+const DEFAULT_CACHE_MAX_MB: u64 = 1024;
+const CACHE_GC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Temporary files older than this are left over from a crashed render
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preview {
+    Image,
+    /// A single frame from early in a video
+    Poster,
+    /// Frames spread evenly across a video, for scrubbing on hover
+    Sprite,
+}
+
+impl Preview {
+    /// What can be rendered for `path`; videos need ffmpeg.
+    pub fn for_path(path: &Path, sprite: bool) -> Option<Self> {
+        if has_extension(path, VIDEO_EXTENSIONS) {
+            ffmpeg::binary()?;
+            Some(if sprite { Self::Sprite } else { Self::Poster })
+        } else if !sprite && is_image(path) {
+            Some(Self::Image)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Thumbnail {
+    pub path: PathBuf,
+    pub content_type: &'static str,
+}
+
+pub fn is_image(path: &Path) -> bool {
+    has_extension(path, IMAGE_EXTENSIONS)
+}
+
+/// Whether `/api/thumb` can render a preview of the file at `path`.
+pub fn available(path: &Path) -> bool {
+    Preview::for_path(path, false).is_some()
+}
+
+/// The smallest cached size that covers `requested` pixels.
+pub fn size_bucket(requested: Option<u32>) -> u32 {
+    let requested = requested.unwrap_or(DEFAULT_SIZE);
+    SIZES
+        .iter()
+        .copied()
+        .find(|size| *size >= requested)
+        .unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+pub fn cache_dir() -> PathBuf {
+    state_dir().join("cache").join("thumbs")
+}
+
+/// Periodically trims the cache to `THUMB_CACHE_MAX_MB` (default 1024),
+/// removing the oldest renders first.
+pub async fn collect_cache() {
+    let max_size = std::env::var("THUMB_CACHE_MAX_MB")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_CACHE_MAX_MB)
+        * 1024
+        * 1024;
+
+    loop {
+        let _ = tokio::task::spawn_blocking(move || trim_cache(max_size)).await;
+        tokio::time::sleep(CACHE_GC_INTERVAL).await;
+    }
+}
+
+fn trim_cache(max_size: u64) -> io::Result<()> {
+    let now = SystemTime::now();
+    let mut files = Vec::new();
+    let mut total = 0;
+    for entry in std::fs::read_dir(cache_dir())? {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some("tmp") {
+            if now.duration_since(modified).unwrap_or_default() > STALE_TEMP_AGE {
+                let _ = std::fs::remove_file(&path);
+            }
+            continue;
+        }
+        total += metadata.len();
+        files.push((modified, metadata.len(), path));
+    }
+
+    files.sort_by_key(|(modified, _, _)| *modified);
+    for (_, len, path) in files {
+        if total <= max_size {
+            break;
+        }
+        if std::fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+    Ok(())
+}
+
+/// Identifies one `variant` (size, format, ...) of one version of a file;
+/// changes whenever the file is modified or resized.
+pub fn cache_key(source: &Path, metadata: &Metadata, variant: &str) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+
+    let mut hasher = Sha256::new();
+    hasher.update(source.as_os_str().as_encoded_bytes());
+    hasher.update(modified.to_le_bytes());
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update(variant.as_bytes());
+    hasher.finalize()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Returns the cached thumbnail stored under `key`, rendering it first if
+/// needed. Opaque images become JPEG, ones with transparency lossless WebP.
+pub fn thumbnail(source: &Path, key: &str, preview: Preview, size: u32) -> io::Result<Thumbnail> {
+    let dir = cache_dir();
+    for (extension, content_type) in [("jpg", "image/jpeg"), ("webp", "image/webp")] {
+        let path = dir.join(format!("{}.{}", key, extension));
+        if path.is_file() {
+            return Ok(Thumbnail { path, content_type });
+        }
+    }
+
+    let image = match preview {
+        Preview::Image => decode_oriented(source)?,
+        Preview::Poster => poster_frame(source, size)?,
+        Preview::Sprite => sprite_sheet(source, size)?,
+    };
+    let image = if preview != Preview::Sprite && (image.width() > size || image.height() > size) {
+        image.thumbnail(size, size)
+    } else {
+        image
+    };
+
+    // Decoders like GIF always produce an alpha channel, so look at the pixels
+    let transparent =
+        image.color().has_alpha() && image.to_rgba8().pixels().any(|pixel| pixel[3] < 255);
+    let (extension, content_type) = if transparent {
+        ("webp", "image/webp")
+    } else {
+        ("jpg", "image/jpeg")
+    };
+    let format = if transparent {
+        OutputFormat::Webp
+    } else {
+        OutputFormat::Jpeg
+    };
+    let path = dir.join(format!("{}.{}", key, extension));
+    write_atomically(&path, |writer| encode(&image, format, writer))?;
+
+    Ok(Thumbnail { path, content_type })
+}
+
+/// `resize` with its width and height rounded up to the nearest of
+/// `RESIZE_SIZES`.
+pub fn snap_resize(resize: &ResizeQuery) -> ResizeQuery {
+    let snap = |requested: u32| {
+        RESIZE_SIZES
+            .iter()
+            .copied()
+            .find(|size| *size >= requested)
+            .unwrap_or(MAX_RESIZE)
+    };
+    ResizeQuery {
+        w: resize.w.map(snap),
+        h: resize.h.map(snap),
+        ..*resize
+    }
+}
+
+/// Cache key variant for `resize`, so equal requests share one file.
+pub fn resize_variant(resize: &ResizeQuery) -> String {
+    format!(
+        "resize:{:?}:{:?}:{:?}:{:?}",
+        resize.w, resize.h, resize.fit, resize.format
+    )
+}
+
+/// Returns the cached copy of `source` resized and converted as `resize`
+/// asks, rendering it first if needed. Images are never enlarged; without a
+/// `format` the source's own is kept, with GIF becoming PNG.
+pub fn resized(source: &Path, key: &str, resize: &ResizeQuery) -> io::Result<Thumbnail> {
+    if !is_image(source) {
+        return Err(io::ErrorKind::Unsupported.into());
+    }
+    let format = resize.format.unwrap_or_else(|| {
+        if has_extension(source, &["jpg", "jpeg"]) {
+            OutputFormat::Jpeg
+        } else if has_extension(source, &["webp"]) {
+            OutputFormat::Webp
+        } else {
+            OutputFormat::Png
+        }
+    });
+    let (extension, content_type) = match format {
+        OutputFormat::Jpeg => ("jpg", "image/jpeg"),
+        OutputFormat::Png => ("png", "image/png"),
+        OutputFormat::Webp => ("webp", "image/webp"),
+    };
+
+    let path = cache_dir().join(format!("{}.{}", key, extension));
+    if path.is_file() {
+        return Ok(Thumbnail { path, content_type });
+    }
+
+    let image = decode_oriented(source)?;
+    let (width, height) = (image.width(), image.height());
+    let limit = |requested: Option<u32>, actual: u32| {
+        requested.map_or(actual, |requested| {
+            requested.clamp(1, MAX_RESIZE).min(actual)
+        })
+    };
+    let image = match (resize.fit.unwrap_or_default(), resize.w, resize.h) {
+        (Fit::Cover, Some(box_width), Some(box_height)) => {
+            // Shrink the box until it fits in the image, keeping its shape
+            let box_width = box_width.clamp(1, MAX_RESIZE) as f64;
+            let box_height = box_height.clamp(1, MAX_RESIZE) as f64;
+            let factor = (width as f64 / box_width)
+                .min(height as f64 / box_height)
+                .min(1.0);
+            let box_width = ((box_width * factor).round() as u32).max(1);
+            let box_height = ((box_height * factor).round() as u32).max(1);
+            if (box_width, box_height) == (width, height) {
+                image
+            } else {
+                image.resize_to_fill(box_width, box_height, FilterType::Lanczos3)
+            }
+        }
+        (Fit::Fill, Some(_), Some(_)) => {
+            let (box_width, box_height) = (limit(resize.w, width), limit(resize.h, height));
+            if (box_width, box_height) == (width, height) {
+                image
+            } else {
+                image.resize_exact(box_width, box_height, FilterType::Lanczos3)
+            }
+        }
+        // Contain, or only one side given
+        _ => {
+            let (box_width, box_height) = (limit(resize.w, width), limit(resize.h, height));
+            if box_width >= width && box_height >= height {
+                image
+            } else {
+                image.resize(box_width, box_height, FilterType::Lanczos3)
+            }
+        }
+    };
+
+    write_atomically(&path, |writer| encode(&image, format, writer))?;
+    Ok(Thumbnail { path, content_type })
+}
+
+/// JPEG drops any alpha channel; WebP is always lossless, the only kind the
+/// encoder supports.
+fn encode(
+    image: &DynamicImage,
+    format: OutputFormat,
+    writer: &mut BufWriter<std::fs::File>,
+) -> io::Result<()> {
+    let alpha = image.color().has_alpha();
+    match format {
+        OutputFormat::Jpeg => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(writer, JPEG_QUALITY)),
+        OutputFormat::Png if alpha => image.to_rgba8().write_with_encoder(PngEncoder::new(writer)),
+        OutputFormat::Png => image.to_rgb8().write_with_encoder(PngEncoder::new(writer)),
+        OutputFormat::Webp if alpha => image
+            .to_rgba8()
+            .write_with_encoder(WebPEncoder::new_lossless(writer)),
+        OutputFormat::Webp => image
+            .to_rgb8()
+            .write_with_encoder(WebPEncoder::new_lossless(writer)),
+    }
+    .map_err(io::Error::other)
+}
+
+/// Decodes an image and rotates or flips it upright according to its EXIF
+/// orientation.
+pub fn decode_oriented(source: &Path) -> io::Result<DynamicImage> {
+    let mut decoder = ImageReader::open(source)?
+        .with_guessed_format()?
+        .into_decoder()
+        .map_err(io::Error::other)?;
+    let orientation = decoder.orientation().map_err(io::Error::other)?;
+
+    let mut image = DynamicImage::from_decoder(decoder).map_err(io::Error::other)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// A frame a tenth of the way in, which skips most fades from black.
+fn poster_frame(source: &Path, size: u32) -> io::Result<DynamicImage> {
+    let duration = ffmpeg::duration(source)?.unwrap_or(0.0);
+    ffmpeg::frame(source, duration / 10.0, size).or_else(|_| ffmpeg::frame(source, 0.0, size))
+}
+
+/// `SPRITE_FRAMES` frames from the middle of equal slices of the video, side
+/// by side in one image.
+fn sprite_sheet(source: &Path, size: u32) -> io::Result<DynamicImage> {
+    let duration = ffmpeg::duration(source)?
+        .filter(|duration| *duration > 0.0)
+        .ok_or_else(|| io::Error::other("video has no known duration"))?;
+    let step = duration / SPRITE_FRAMES as f64;
+
+    let mut sheet: Option<DynamicImage> = None;
+    for index in 0..SPRITE_FRAMES {
+        let frame = ffmpeg::frame(source, step * (index as f64 + 0.5), size)?;
+        let sheet = sheet.get_or_insert_with(|| {
+            DynamicImage::new_rgb8(frame.width() * SPRITE_FRAMES, frame.height())
+        });
+        let (width, height) = (sheet.width() / SPRITE_FRAMES, sheet.height());
+        let frame = if frame.width() != width || frame.height() != height {
+            frame.resize_exact(width, height, FilterType::Triangle)
+        } else {
+            frame
+        };
+        sheet
+            .copy_from(&frame, width * index, 0)
+            .map_err(io::Error::other)?;
+    }
+    sheet.ok_or_else(|| io::Error::other("video has no frames"))
+}
+
+/// Cache files appear under their name only once fully written, so a
+/// concurrent reader never sees a truncated thumbnail.
+fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<std::fs::File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let dir = path.parent().ok_or(io::ErrorKind::NotFound)?;
+    std::fs::create_dir_all(dir)?;
+
+    let mut random = [0u8; 8];
+    getrandom::getrandom(&mut random).map_err(io::Error::other)?;
+    let suffix: String = random.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let temp_path = path.with_extension(format!("{}.tmp", suffix));
+
+    let result = (|| {
+        let mut writer = BufWriter::new(std::fs::File::create(&temp_path)?);
+        write(&mut writer)?;
+        writer.flush()?;
+        std::fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
@@ -1,6 +1,9 @@
+use serde::de::{DeserializeOwned, IntoDeserializer};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::{Component, Path, PathBuf};
 use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Deserialize)]
 pub struct ListQuery {
@@ -13,11 +16,110 @@ pub struct SearchQuery {
     pub query: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct UploadQuery {
+    pub path: Option<String>,
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+}
+
+/// What to do when a file being written already exists.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Keep both, numbering the new one `name (1).ext`, `name (2).ext`, ...
+    #[default]
+    Rename,
+    Overwrite,
+    Skip,
+    Fail,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct FileQuery {
     pub path: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ThumbQuery {
+    pub path: String,
+    /// Longest edge in pixels; rounded up to one of the cached sizes
+    pub size: Option<u32>,
+    /// For videos, a strip of frames instead of a single poster frame
+    #[serde(default)]
+    pub sprite: bool,
+}
+
+/// Resizing and conversion options for images served from the root.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+pub struct ResizeQuery {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub fit: Option<Fit>,
+    pub format: Option<OutputFormat>,
+}
+
+impl ResizeQuery {
+    /// Reads the options from a file URL's query, ignoring other parameters.
+    /// When any option is invalid the file is served as it is.
+    pub fn from_query(query: &HashMap<String, String>) -> Self {
+        Self::parse(query).unwrap_or_default()
+    }
+
+    fn parse(query: &HashMap<String, String>) -> Option<Self> {
+        let size = |key: &str| match query.get(key) {
+            Some(value) => value.parse().ok().map(Some),
+            None => Some(None),
+        };
+        Some(Self {
+            w: size("w")?,
+            h: size("h")?,
+            fit: query_option(query, "fit")?,
+            format: query_option(query, "format")?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.w.is_none() && self.h.is_none() && self.fit.is_none() && self.format.is_none()
+    }
+}
+
+/// How an image is fitted into the `w` x `h` box.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scale to fit inside the box, keeping the aspect ratio
+    #[default]
+    Contain,
+    /// Scale to fill the box, cropping the overflow around the centre
+    Cover,
+    /// Stretch to exactly the box
+    Fill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Webp,
+    Jpeg,
+    Png,
+}
+
+/// `Some(None)` when `key` is absent, `None` when it is not a valid `T`.
+fn query_option<T: DeserializeOwned>(
+    query: &HashMap<String, String>,
+    key: &str,
+) -> Option<Option<T>> {
+    match query.get(key) {
+        Some(value) => {
+            let value: Result<T, serde::de::value::Error> =
+                T::deserialize(value.as_str().into_deserializer());
+            value.ok().map(Some)
+        }
+        None => Some(None),
+    }
+}
+
 static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
 
 pub fn data_dir() -> &'static Path {
@@ -40,7 +142,37 @@ pub fn data_path(path: &str) -> Option<PathBuf> {
         return None;
     }
 
-    Some(data_dir().join(path))
+    let path = data_dir().join(path);
+    if is_internal(&path) {
+        return None;
+    }
+
+    Some(path)
+}
+
+static STATE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Where server-side state (shares, staging areas, caches) lives. Defaults to a
+/// hidden folder inside the data directory that is never served or listed.
+pub fn state_dir() -> &'static Path {
+    STATE_DIR
+        .get_or_init(|| {
+            std::env::var("STATE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| data_dir().join(".mediabrowser"))
+        })
+        .as_path()
+}
+
+/// Suffix of hidden files that hold uploads until they are complete.
+pub const PARTIAL_SUFFIX: &str = ".mbpartial";
+
+pub fn is_internal(path: &Path) -> bool {
+    path.starts_with(state_dir())
+        || path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.') && name.ends_with(PARTIAL_SUFFIX))
 }
 
 pub fn api_path(path: &Path) -> String {
@@ -50,3 +182,32 @@ pub fn api_path(path: &Path) -> String {
         .trim_start_matches('/')
         .to_string()
 }
+
+/// Case-insensitive check of a file's extension against `known`, given
+/// without the dot.
+pub fn has_extension(path: &Path, known: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            known
+                .iter()
+                .any(|known| extension.eq_ignore_ascii_case(known))
+        })
+}
+
+static READ_ONLY: OnceLock<bool> = OnceLock::new();
+
+pub fn read_only() -> bool {
+    *READ_ONLY.get_or_init(|| {
+        std::env::var("READ_ONLY")
+            .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false)
+    })
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,382 @@
+use std::io::{self, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP64_END_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const END_SIGNATURE: u32 = 0x0605_4b50;
+
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+const FLAG_UTF8: u16 = 1 << 11;
+const METHOD_STORED: u16 = 0;
+
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// Upper byte 3 = Unix, so external attributes carry the file mode
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION_ZIP64;
+
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+const TIMESTAMP_EXTRA_ID: u16 = 0x5455;
+const U32_MAX: u64 = u32::MAX as u64;
+
+/// Writes a ZIP archive front to back without seeking, so it can be streamed
+/// straight into a response. Entries are stored uncompressed; sizes and CRCs
+/// follow each entry in a data descriptor and ZIP64 records are used once
+/// sizes, offsets or the entry count outgrow the classic format.
+pub struct ZipStream<W: Write> {
+    out: W,
+    offset: u64,
+    entries: Vec<CentralEntry>,
+}
+
+struct CentralEntry {
+    name: String,
+    flags: u16,
+    crc: u32,
+    size: u64,
+    offset: u64,
+    modified: u32,
+    mode: u32,
+    is_dir: bool,
+    zip64: bool,
+}
+
+impl<W: Write> ZipStream<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            offset: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add_directory(&mut self, name: &str, modified: SystemTime, mode: u32) -> io::Result<()> {
+        let entry = CentralEntry::directory(name, self.offset, unix_seconds(modified), mode);
+        self.write_all(&entry.local_header())?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Adds a file of `size` bytes; at most `size` bytes are read from `reader`.
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        modified: SystemTime,
+        mode: u32,
+        size: u64,
+        reader: impl Read,
+    ) -> io::Result<()> {
+        let mut entry = CentralEntry::file(name, self.offset, unix_seconds(modified), mode, size);
+        self.write_all(&entry.local_header())?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        let mut reader = reader.take(size);
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut written = 0u64;
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            hasher.update(&buffer[..read]);
+            self.write_all(&buffer[..read])?;
+            written += read as u64;
+        }
+        entry.crc = hasher.finalize();
+        entry.size = written;
+
+        self.write_all(&entry.data_descriptor())?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Writes the central directory and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let central_start = self.offset;
+        let entries = std::mem::take(&mut self.entries);
+        for entry in &entries {
+            self.write_all(&entry.central_header())?;
+        }
+
+        let central_size = self.offset - central_start;
+        self.write_all(&end_records(
+            entries.len() as u64,
+            central_start,
+            central_size,
+        ))?;
+
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+/// Exact length of the archive `ZipStream` produces for `entries`, given as
+/// names with a file size, or `None` for directories, in the order added.
+pub fn archive_size<'a>(entries: impl IntoIterator<Item = (&'a str, Option<u64>)>) -> u64 {
+    let mut offset = 0u64;
+    let mut central_size = 0u64;
+    let mut count = 0u64;
+
+    for (name, size) in entries {
+        let entry = match size {
+            Some(size) => CentralEntry::file(name, offset, 0, 0, size),
+            None => CentralEntry::directory(name, offset, 0, 0),
+        };
+
+        offset += entry.local_header().len() as u64;
+        if size.is_some() {
+            offset += entry.size + entry.data_descriptor().len() as u64;
+        }
+        central_size += entry.central_header().len() as u64;
+        count += 1;
+    }
+
+    offset + central_size + end_records(count, offset, central_size).len() as u64
+}
+
+impl CentralEntry {
+    fn directory(name: &str, offset: u64, modified: u32, mode: u32) -> Self {
+        Self {
+            name: format!("{}/", name.trim_end_matches('/')),
+            flags: FLAG_UTF8,
+            crc: 0,
+            size: 0,
+            offset,
+            modified,
+            mode,
+            is_dir: true,
+            zip64: false,
+        }
+    }
+
+    fn file(name: &str, offset: u64, modified: u32, mode: u32, size: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            flags: FLAG_UTF8 | FLAG_DATA_DESCRIPTOR,
+            crc: 0,
+            size,
+            offset,
+            modified,
+            mode,
+            is_dir: false,
+            zip64: size >= U32_MAX,
+        }
+    }
+
+    fn local_header(&self) -> Vec<u8> {
+        let mut extra = Vec::new();
+        if self.zip64 {
+            // Real sizes follow in the data descriptor
+            put_u16(&mut extra, ZIP64_EXTRA_ID);
+            put_u16(&mut extra, 16);
+            put_u64(&mut extra, 0);
+            put_u64(&mut extra, 0);
+        }
+        put_timestamp_extra(&mut extra, self.modified);
+
+        let (time, date) = dos_date_time(self.modified);
+        let size_field = if self.zip64 { u32::MAX } else { 0 };
+        let mut header = Vec::with_capacity(30 + self.name.len() + extra.len());
+        put_u32(&mut header, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut header, version_needed(self.zip64));
+        put_u16(&mut header, self.flags);
+        put_u16(&mut header, METHOD_STORED);
+        put_u16(&mut header, time);
+        put_u16(&mut header, date);
+        put_u32(&mut header, 0);
+        put_u32(&mut header, size_field);
+        put_u32(&mut header, size_field);
+        put_u16(&mut header, self.name.len() as u16);
+        put_u16(&mut header, extra.len() as u16);
+        header.extend_from_slice(self.name.as_bytes());
+        header.extend_from_slice(&extra);
+        header
+    }
+
+    fn data_descriptor(&self) -> Vec<u8> {
+        let mut descriptor = Vec::with_capacity(24);
+        put_u32(&mut descriptor, DATA_DESCRIPTOR_SIGNATURE);
+        put_u32(&mut descriptor, self.crc);
+        if self.zip64 {
+            put_u64(&mut descriptor, self.size);
+            put_u64(&mut descriptor, self.size);
+        } else {
+            put_u32(&mut descriptor, self.size as u32);
+            put_u32(&mut descriptor, self.size as u32);
+        }
+        descriptor
+    }
+
+    fn central_header(&self) -> Vec<u8> {
+        let size_overflows = self.zip64 || self.size >= U32_MAX;
+        let offset_overflows = self.offset >= U32_MAX;
+
+        let needs_zip64 = size_overflows || offset_overflows;
+
+        let mut extra = Vec::new();
+        if needs_zip64 {
+            put_u16(&mut extra, ZIP64_EXTRA_ID);
+            put_u16(
+                &mut extra,
+                if size_overflows { 16 } else { 0 } + if offset_overflows { 8 } else { 0 },
+            );
+            if size_overflows {
+                put_u64(&mut extra, self.size);
+                put_u64(&mut extra, self.size);
+            }
+            if offset_overflows {
+                put_u64(&mut extra, self.offset);
+            }
+        }
+        put_timestamp_extra(&mut extra, self.modified);
+
+        let size = if size_overflows {
+            u32::MAX
+        } else {
+            self.size as u32
+        };
+        let external_attributes = (self.mode << 16) | if self.is_dir { 0x10 } else { 0 };
+
+        let (time, date) = dos_date_time(self.modified);
+        let mut header = Vec::with_capacity(46 + self.name.len() + extra.len());
+        put_u32(&mut header, CENTRAL_HEADER_SIGNATURE);
+        put_u16(&mut header, VERSION_MADE_BY);
+        put_u16(&mut header, version_needed(needs_zip64));
+        put_u16(&mut header, self.flags);
+        put_u16(&mut header, METHOD_STORED);
+        put_u16(&mut header, time);
+        put_u16(&mut header, date);
+        put_u32(&mut header, self.crc);
+        put_u32(&mut header, size);
+        put_u32(&mut header, size);
+        put_u16(&mut header, self.name.len() as u16);
+        put_u16(&mut header, extra.len() as u16);
+        put_u16(&mut header, 0);
+        put_u16(&mut header, 0);
+        put_u16(&mut header, 0);
+        put_u32(&mut header, external_attributes);
+        put_u32(&mut header, self.offset.min(U32_MAX) as u32);
+        header.extend_from_slice(self.name.as_bytes());
+        header.extend_from_slice(&extra);
+        header
+    }
+}
+
+/// End of central directory, preceded by the ZIP64 end record and locator
+/// when the classic fields cannot hold the values.
+fn end_records(count: u64, central_start: u64, central_size: u64) -> Vec<u8> {
+    let needs_zip64 =
+        count >= u16::MAX as u64 || central_start >= U32_MAX || central_size >= U32_MAX;
+
+    let mut end = Vec::with_capacity(98);
+    if needs_zip64 {
+        put_u32(&mut end, ZIP64_END_SIGNATURE);
+        put_u64(&mut end, 44);
+        put_u16(&mut end, VERSION_MADE_BY);
+        put_u16(&mut end, VERSION_ZIP64);
+        put_u32(&mut end, 0);
+        put_u32(&mut end, 0);
+        put_u64(&mut end, count);
+        put_u64(&mut end, count);
+        put_u64(&mut end, central_size);
+        put_u64(&mut end, central_start);
+
+        put_u32(&mut end, ZIP64_LOCATOR_SIGNATURE);
+        put_u32(&mut end, 0);
+        put_u64(&mut end, central_start + central_size);
+        put_u32(&mut end, 1);
+    }
+
+    put_u32(&mut end, END_SIGNATURE);
+    put_u16(&mut end, 0);
+    put_u16(&mut end, 0);
+    put_u16(&mut end, count.min(u16::MAX as u64) as u16);
+    put_u16(&mut end, count.min(u16::MAX as u64) as u16);
+    put_u32(&mut end, central_size.min(U32_MAX) as u32);
+    put_u32(&mut end, central_start.min(U32_MAX) as u32);
+    put_u16(&mut end, 0);
+    end
+}
+
+fn version_needed(zip64: bool) -> u16 {
+    if zip64 {
+        VERSION_ZIP64
+    } else {
+        VERSION_DEFAULT
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u32 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs().min(u32::MAX as u64) as u32)
+        .unwrap_or(0)
+}
+
+/// Extended timestamp field so extractors restore the exact UTC mtime.
+fn put_timestamp_extra(buffer: &mut Vec<u8>, modified: u32) {
+    put_u16(buffer, TIMESTAMP_EXTRA_ID);
+    put_u16(buffer, 5);
+    buffer.push(1);
+    put_u32(buffer, modified);
+}
+
+/// MS-DOS time and date (UTC, two second resolution, 1980 to 2107).
+fn dos_date_time(seconds: u32) -> (u16, u16) {
+    let days = seconds / 86_400;
+    let remainder = seconds % 86_400;
+    let (year, month, day) = civil_from_days(days as i64);
+
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    if year > 2107 {
+        return ((23 << 11) | (59 << 5) | 29, (127 << 9) | (12 << 5) | 31);
+    }
+
+    let time =
+        ((remainder / 3600) << 11) | (((remainder % 3600) / 60) << 5) | ((remainder % 60) / 2);
+    let date = ((year as u32 - 1980) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) civil date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
@@ -27,7 +27,20 @@ function updateToolbarDropdownPosition() {
   toolbarDropdown.style.bottom = `${keyboardInset}px`;
 }
 
-function init() {
+async function loadCapabilities() {
+  try {
+    const response = await fetch("/api/capabilities");
+    if (response.ok) {
+      capabilities = await response.json();
+    }
+  } catch {
+    // Keep defaults; mutating requests still fail server-side if disallowed
+  }
+
+  document.body.classList.toggle("read-only", capabilities.read_only);
+}
+
+async function init() {
   initializeGridSize();
 
   window.addEventListener("resize", resizeHandler);
@@ -71,6 +84,7 @@ function init() {
   }
 
   updateToolbarDropdownPosition();
+  await loadCapabilities();
   loadInitialDirectory();
 }
 
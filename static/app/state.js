@@ -1,4 +1,5 @@
 let currentPath = "";
+let capabilities = { read_only: false };
 let currentFiles = [];
 let currentDirectoryFiles = [];
 let selectedFile = null;
@@ -100,13 +100,48 @@ function downloadFilePath(path) {
   triggerDownload(`/api/download/${encodeURIPath(path)}`);
 }
 
-async function downloadBulkPaths(paths) {
+function dispositionFilename(disposition) {
+  if (!disposition) {
+    return null;
+  }
+
+  const encoded = disposition.match(/filename\*=UTF-8''([^;]+)/i);
+  if (encoded) {
+    try {
+      return decodeURIComponent(encoded[1]);
+    } catch {
+      // Fall through to the plain filename
+    }
+  }
+
+  const plain = disposition.match(/filename="([^"]*)"/i);
+  return plain ? plain[1] : null;
+}
+
+// Polls a background job until it finishes, showing its progress
+async function watchJob(job, message) {
+  while (job.state === "running") {
+    showJobProgress(job, message);
+    await new Promise((resolve) => setTimeout(resolve, 1000));
+
+    const response = await fetch(`/api/jobs/${encodeURIComponent(job.id)}`);
+    if (!response.ok) {
+      break;
+    }
+    job = await response.json();
+  }
+
+  hideJobProgress(job);
+  return job;
+}
+
+async function downloadBulkPaths(paths, format = "tar") {
   const response = await fetch("/api/downloads", {
     method: "POST",
     headers: {
       "Content-Type": "application/json",
     },
-    body: JSON.stringify({ paths }),
+    body: JSON.stringify({ paths, format }),
   });
 
   if (!response.ok) {
@@ -115,6 +150,10 @@ async function downloadBulkPaths(paths) {
   }
 
   const url = URL.createObjectURL(await response.blob());
-  triggerDownload(url, "download.tar");
+  triggerDownload(
+    url,
+    dispositionFilename(response.headers.get("Content-Disposition")) ||
+      `download.${format}`,
+  );
   setTimeout(() => URL.revokeObjectURL(url), 1000);
 }
@@ -3,6 +3,56 @@ function triggerUpload() {
   document.getElementById("fileInput").click();
 }
 
+function triggerFolderUpload() {
+  showUploadPickerNotification();
+  document.getElementById("folderInput").click();
+}
+
+// Collects the files below a dropped file or folder, remembering their
+// path relative to the drop so the server can recreate the folders
+async function collectDroppedFiles(entry, files) {
+  if (entry.isFile) {
+    const file = await new Promise((resolve, reject) =>
+      entry.file(resolve, reject),
+    );
+    file.relativePath = entry.fullPath.replace(/^\//, "");
+    files.push(file);
+  } else if (entry.isDirectory) {
+    const reader = entry.createReader();
+    let batch;
+    do {
+      batch = await new Promise((resolve, reject) =>
+        reader.readEntries(resolve, reject),
+      );
+      for (const child of batch) {
+        await collectDroppedFiles(child, files);
+      }
+    } while (batch.length > 0);
+  }
+}
+
+document.addEventListener("dragover", (event) => {
+  if (!capabilities.read_only) {
+    event.preventDefault();
+  }
+});
+
+document.addEventListener("drop", async (event) => {
+  if (capabilities.read_only) {
+    return;
+  }
+  event.preventDefault();
+
+  const entries = Array.from(event.dataTransfer.items)
+    .map((item) => item.webkitGetAsEntry?.())
+    .filter(Boolean);
+  const files = [];
+  for (const entry of entries) {
+    await collectDroppedFiles(entry, files);
+  }
+  handleFileSelect(files);
+});
+
 function handleFileSelect(files) {
   if (files.length === 0) {
     hideUploadPickerNotification();
@@ -23,6 +73,10 @@ async function uploadFiles(files) {
 
   async function uploadFile(file) {
     const formData = new FormData();
+    const relativePath = file.relativePath || file.webkitRelativePath;
+    if (relativePath) {
+      formData.append("relative_path", relativePath);
+    }
     formData.append("file", file);
 
     try {
@@ -36,7 +90,12 @@ async function uploadFiles(files) {
 
       if (!response.ok) {
         const text = await response.text();
-        failedFiles.push(`${file.name}: ${text}`);
+        let message = text;
+        try {
+          const body = JSON.parse(text);
+          message = body.message ?? body;
+        } catch {}
+        failedFiles.push(`${file.name}: ${message}`);
       } else {
         await response.json();
       }
@@ -90,3 +149,35 @@ function createFolder() {
       .catch(() => alert("failed to create folder"));
   }
 }
+
+async function createUploadLink() {
+  const hours = prompt("Expire after hours (empty for never):", "");
+  if (hours === null) {
+    return;
+  }
+
+  const request = { path: currentPath };
+  const expiresInHours = parseFloat(hours);
+  if (!isNaN(expiresInHours) && expiresInHours > 0) {
+    request.expires_in = Math.round(expiresInHours * 3600);
+  }
+
+  try {
+    const response = await fetch("/api/dropboxes", {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify(request),
+    });
+
+    if (!response.ok) {
+      throw new Error("failed to create upload link");
+    }
+
+    const dropbox = await response.json();
+    prompt("Upload link:", `${window.location.origin}${dropbox.url}`);
+  } catch {
+    alert("Failed to create upload link");
+  }
+}
@@ -110,6 +110,9 @@ function renderVisibleItems() {
   for (const [index, item] of virtualScrollData.renderedItems) {
     if (index < startIndex || index > endIndex) {
       item.onclick = null;
+      item.onmouseenter = null;
+      item.onmousemove = null;
+      item.onmouseleave = null;
 
       const img = item.querySelector("img");
       const video = item.querySelector("video");
@@ -157,6 +160,9 @@ function createGridItem(file, index) {
   item.dataset.filePath = file.path;
   item.dataset.fileType = file.file_type;
   item.dataset.fileName = file.name;
+  if (file.thumbnail) {
+    item.dataset.thumbnail = `${file.modified}`;
+  }
   item.onclick = (e) => handleFileClick(e, file);
 
   const columns = virtualScrollData.columns;
@@ -175,7 +181,9 @@ function createGridItem(file, index) {
     item.classList.add("selected");
   }
 
-  const hasPreview = file.file_type === "image";
+  const hasPreview =
+    file.file_type === "image" ||
+    (file.file_type === "video" && file.thumbnail);
   const label = file.is_dir ? `${file.name}/` : file.name;
 
   item.innerHTML = `<div class="file-name">${escapeHtml(label)}</div>`;
@@ -184,12 +192,15 @@ function createGridItem(file, index) {
     item.classList.add("always-show-name");
   }
 
-  if (file.is_dir) {
+  if (file.is_dir || file.archive) {
     item.classList.add("directory");
   } else if (hasPreview) {
     item.classList.add("lazy-load");
   } else {
     item.classList.add("file");
+    if (file.file_type === "video") {
+      item.classList.add("video-file");
+    }
   }
 
   if (selectedFiles.has(file.path)) {
@@ -254,7 +265,7 @@ function loadItemContent(item) {
 
   item.classList.remove("lazy-load");
 
-  if (fileType === "image") {
+  if (fileType === "image" || fileType === "video") {
     const img = document.createElement("img");
     img.loading = "lazy";
     img.alt = escapeHtml(fileName);
@@ -270,16 +281,19 @@ function loadItemContent(item) {
       img.alt = "Failed to load";
     };
 
-    const servePath = `/${encodeURIPath(filePath)}`;
-    img.src = servePath;
+    if (item.dataset.thumbnail !== undefined) {
+      img.src = thumbnailUrl(item);
+    } else {
+      img.src = `/${encodeURIPath(filePath)}`;
+    }
     item.innerHTML = "";
     item.appendChild(img);
     if (label) {
       item.appendChild(label);
     }
-  } else if (fileType === "video") {
-    item.classList.add("file");
-    return;
+    if (fileType === "video") {
+      enableSpriteScrubbing(item, img);
+    }
   }
 
   if (selectedFiles.has(filePath)) {
@@ -289,6 +303,66 @@ function loadItemContent(item) {
   }
 }
 
+function thumbnailUrl(item, sprite = false) {
+  const size = Math.round(
+    virtualScrollData.actualItemSize * (window.devicePixelRatio || 1),
+  );
+  const path = encodeURIComponent(item.dataset.filePath);
+  const spriteParam = sprite ? "&sprite=true" : "";
+  return `/api/thumb?path=${path}&size=${size}${spriteParam}&v=${item.dataset.thumbnail}`;
+}
+
+// Hovering a video tile shows frames from across the video, picked by the
+// pointer position, using the sprite sheet from /api/thumb
+const SPRITE_FRAMES = 10;
+
+function enableSpriteScrubbing(item, poster) {
+  let sprite = null;
+  let spriteLoaded = false;
+
+  const scrubber = document.createElement("div");
+  scrubber.className = "sprite-scrubber";
+  item.insertBefore(scrubber, poster.nextSibling);
+
+  item.onmouseenter = () => {
+    if (sprite) {
+      return;
+    }
+    sprite = new Image();
+    sprite.onload = () => {
+      spriteLoaded = true;
+      scrubber.style.backgroundImage = `url("${sprite.src}")`;
+    };
+    sprite.src = thumbnailUrl(item, true);
+  };
+
+  item.onmousemove = (e) => {
+    if (!spriteLoaded) {
+      return;
+    }
+    const rect = item.getBoundingClientRect();
+    const frameWidth = sprite.naturalWidth / SPRITE_FRAMES;
+    const frameHeight = sprite.naturalHeight;
+    // Cover the tile like the poster does, centring the frame
+    const scale = Math.max(rect.width / frameWidth, rect.height / frameHeight);
+    const position = (e.clientX - rect.left) / rect.width;
+    const index = Math.min(
+      SPRITE_FRAMES - 1,
+      Math.max(0, Math.floor(position * SPRITE_FRAMES)),
+    );
+    const offsetX = (frameWidth * scale - rect.width) / 2;
+    const offsetY = (frameHeight * scale - rect.height) / 2;
+
+    scrubber.style.backgroundSize = `${sprite.naturalWidth * scale}px ${frameHeight * scale}px`;
+    scrubber.style.backgroundPosition = `${-(index * frameWidth * scale + offsetX)}px ${-offsetY}px`;
+    scrubber.style.opacity = "1";
+  };
+
+  item.onmouseleave = () => {
+    scrubber.style.opacity = "0";
+  };
+}
+
 function filterFiles(files) {
   if (currentFilter === "all") return files;
   if (currentFilter === "image")
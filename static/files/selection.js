@@ -219,23 +219,107 @@ async function renameSelected() {
   }
 }
 
-async function downloadSelected() {
+async function downloadSelected(format) {
   const selectedEntries = getSelectedEntries();
   if (selectedEntries.length === 0) return;
 
   const hasDirectory = selectedEntries.some((file) => file.is_dir);
 
-  if (selectedEntries.length === 1 && !hasDirectory) {
+  if (selectedEntries.length === 1 && !hasDirectory && !format) {
     downloadFilePath(selectedEntries[0].path);
   } else {
     try {
-      await downloadBulkPaths(selectedEntries.map((file) => file.path));
+      await downloadBulkPaths(
+        selectedEntries.map((file) => file.path),
+        format,
+      );
     } catch (err) {
       alert(err.message);
     }
   }
 }
 
+async function compressSelected() {
+  const selectedEntries = getSelectedEntries();
+  if (selectedEntries.length === 0) return;
+
+  const defaultName =
+    selectedEntries.length === 1 ? selectedEntries[0].name : "archive";
+  const name = prompt("Archive name:", `${defaultName}.zip`);
+  if (!name || !name.trim()) {
+    return;
+  }
+
+  const destination = currentPath
+    ? `${currentPath}/${name.trim()}`
+    : name.trim();
+
+  try {
+    const response = await fetch("/api/compress", {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({
+        paths: selectedEntries.map((file) => file.path),
+        destination,
+      }),
+    });
+
+    if (!response.ok) {
+      const message = await response.json().catch(() => null);
+      throw new Error(message || "Failed to compress");
+    }
+
+    clearSelection();
+    const job = await watchJob(await response.json(), "Compressing");
+    if (job.state === "failed") {
+      alert(job.error || "Failed to compress");
+    }
+    refreshView();
+  } catch (err) {
+    alert(err.message);
+  }
+}
+
+async function shareSelected() {
+  const selectedEntries = getSelectedEntries();
+  if (selectedEntries.length !== 1) {
+    alert("Select a single file or folder to share");
+    return;
+  }
+
+  const hours = prompt("Expire after hours (empty for never):", "");
+  if (hours === null) {
+    return;
+  }
+
+  const request = { path: selectedEntries[0].path };
+  const expiresInHours = parseFloat(hours);
+  if (!isNaN(expiresInHours) && expiresInHours > 0) {
+    request.expires_in = Math.round(expiresInHours * 3600);
+  }
+
+  try {
+    const response = await fetch("/api/shares", {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify(request),
+    });
+
+    if (!response.ok) {
+      throw new Error("failed to share");
+    }
+
+    const share = await response.json();
+    prompt("Share link:", `${window.location.origin}${share.url}`);
+  } catch {
+    alert("Failed to create share link");
+  }
+}
+
 function deleteSelected() {
   const selectedEntries = getSelectedEntries();
   if (selectedEntries.length === 0) return;
@@ -192,3 +192,20 @@ function showUploadProgress(current, total) {
 function hideUploadProgress() {
   hideNotification("upload");
 }
+
+function showJobProgress(job, message) {
+  const total = Math.ceil(job.bytes_total / 1048576);
+  showNotification(message, {
+    id: `job-${job.id}`,
+    spinner: true,
+    persistent: true,
+    progress:
+      total > 0
+        ? { current: Math.floor(job.bytes_done / 1048576), total }
+        : null,
+  });
+}
+
+function hideJobProgress(job) {
+  hideNotification(`job-${job.id}`);
+}
@@ -1,8 +1,23 @@
 function showImageViewer(file, servePath, content, zoomControls, loopControls, saveTextBtn) {
-  content.innerHTML = `<img src="${servePath}" alt="${escapeHtml(file.name)}" id="viewerImage">`;
+  const src = `${servePath}${screenSizedQuery(file)}`;
+  content.innerHTML = `<img src="${src}" alt="${escapeHtml(file.name)}" id="viewerImage">`;
   setupMediaZoom();
   updateMediaTransform();
   zoomControls.style.display = "block";
   loopControls.style.display = "none";
   saveTextBtn.style.display = "none";
 }
+
+// On metered or data-saving connections, ask the server for a copy no
+// larger than the screen instead of the original
+function screenSizedQuery(file) {
+  const connection = navigator.connection;
+  const metered =
+    connection && (connection.saveData || connection.type === "cellular");
+  if (!metered || !file.thumbnail) return "";
+
+  const scale = window.devicePixelRatio || 1;
+  const width = Math.round(window.screen.width * scale);
+  const height = Math.round(window.screen.height * scale);
+  return `?w=${width}&h=${height}`;
+}
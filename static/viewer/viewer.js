@@ -1,5 +1,6 @@
 function openMedia(file) {
-  if (file.is_dir) {
+  // Archives open like folders; their members are listed by /api/list
+  if (file.is_dir || file.archive) {
     navigateToDirectory(file.path);
     return;
   }
@@ -36,6 +37,10 @@ function showCurrentMedia() {
     showTextViewer(file, servePath, content, zoomControls, loopControls, saveTextBtn);
   }
 
+  if (document.getElementById("mediaInfo").classList.contains("open")) {
+    loadMediaInfo(file);
+  }
+
   viewer.classList.add("active");
   document.body.classList.add("viewer-open");
   document.getElementById("toolbarDropdown").classList.remove("open");
@@ -90,6 +95,7 @@ function closeViewer() {
   document.getElementById("loopControls").style.display = "none";
   document.getElementById("saveTextBtn").style.display = "none";
 
+  document.getElementById("mediaInfo").classList.remove("open");
   document.getElementById("viewerDropdown").classList.remove("open");
   document.getElementById("viewer").classList.remove("active");
   document.body.classList.remove("viewer-open");
@@ -114,3 +120,81 @@ async function downloadCurrent() {
     }
   }
 }
+
+function toggleMediaInfo() {
+  const panel = document.getElementById("mediaInfo");
+  panel.classList.toggle("open");
+  if (panel.classList.contains("open") && selectedFile) {
+    loadMediaInfo(selectedFile);
+  }
+}
+
+// Fills the info panel with what /api/meta knows about the file
+async function loadMediaInfo(file) {
+  const panel = document.getElementById("mediaInfo");
+  panel.textContent = "loading...";
+
+  let lines;
+  try {
+    const response = await fetch(
+      `/api/meta?path=${encodeURIComponent(file.path)}`,
+    );
+    if (!response.ok) {
+      lines = [await response.json()];
+    } else {
+      lines = describeMediaInfo(await response.json());
+    }
+  } catch (err) {
+    lines = [err.message];
+  }
+
+  if (selectedFile && selectedFile.path === file.path) {
+    panel.textContent = lines.join("\n");
+  }
+}
+
+function describeMediaInfo(meta) {
+  const lines = [];
+  const add = (label, value) => {
+    if (value !== undefined && value !== null && value !== "") {
+      lines.push(`${label}: ${value}`);
+    }
+  };
+
+  add("type", meta.kind);
+  add("format", meta.format);
+  add("codec", meta.codec);
+  if (meta.width && meta.height) add("size", `${meta.width}x${meta.height}`);
+  if (meta.duration) add("duration", formatTime(meta.duration));
+  add("title", meta.title);
+  const camera = [meta.camera_make, meta.camera_model].filter(Boolean);
+  add("camera", camera.join(" "));
+  add("lens", meta.lens);
+  add("exposure", meta.exposure_time && `${meta.exposure_time}s`);
+  add("aperture", meta.f_number && `f/${meta.f_number}`);
+  add("iso", meta.iso);
+  add("focal length", meta.focal_length && `${meta.focal_length}mm`);
+  add("taken", meta.taken_at);
+  if (meta.gps) {
+    const { latitude, longitude } = meta.gps;
+    add("location", `${latitude.toFixed(5)}, ${longitude.toFixed(5)}`);
+  }
+  if (meta.sample_rate) add("sample rate", `${meta.sample_rate} Hz`);
+  add("channels", meta.channels);
+  for (const [key, value] of Object.entries(meta.tags || {})) {
+    add(key.replace(/_/g, " "), value);
+  }
+  (meta.streams || []).forEach((stream, index) => {
+    const details = [
+      stream.codec,
+      stream.width && stream.height && `${stream.width}x${stream.height}`,
+      stream.sample_rate && `${stream.sample_rate} Hz`,
+      stream.channels && `${stream.channels} ch`,
+      stream.language,
+      stream.name,
+    ];
+    const label = `stream ${index} (${stream.type})`;
+    add(label, details.filter(Boolean).join(", "));
+  });
+  return lines;
+}